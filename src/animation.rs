@@ -0,0 +1,302 @@
+use std::sync::Arc;
+
+use bevy::prelude::*;
+
+use crate::{AnchorUiConfig, AnchorUiProjection};
+
+/// Animates anchored UI nodes in and out with a scale-pop and/or slide, when the plugin shows or hides them
+///
+/// Applied through the 'UiTransform' of the UI node, and like 'AnchorFade' the UI node is only hidden once the exit animation is done
+#[derive(Reflect, Debug, Clone, PartialEq)]
+pub struct AnchorTransition {
+    /// Seconds the enter animation takes
+    pub enter_duration: f32,
+    /// Seconds the exit animation takes
+    pub exit_duration: f32,
+    /// The scale of the UI node when it is hidden, 1.0 disables the scale-pop
+    pub hidden_scale: f32,
+    /// Offset in logical pixels of the UI node when it is hidden, 'Vec2::ZERO' disables the slide
+    pub hidden_offset: Vec2,
+    /// Curve of the enter animation, from hidden to visible
+    pub enter_curve: AnchorTransitionCurve,
+    /// Curve of the exit animation, from visible to hidden
+    pub exit_curve: AnchorTransitionCurve,
+}
+
+impl Default for AnchorTransition {
+    fn default() -> Self {
+        Self {
+            enter_duration: 0.2,
+            exit_duration: 0.2,
+            hidden_scale: 1.0,
+            hidden_offset: Vec2::ZERO,
+            enter_curve: EaseFunction::CubicOut.into(),
+            exit_curve: EaseFunction::CubicIn.into(),
+        }
+    }
+}
+
+impl AnchorTransition {
+    /// Pops the UI node in from 'hidden_scale', overshooting slightly
+    pub fn scale_pop(hidden_scale: f32) -> Self {
+        Self {
+            hidden_scale,
+            enter_curve: EaseFunction::BackOut.into(),
+            exit_curve: EaseFunction::BackIn.into(),
+            ..Default::default()
+        }
+    }
+    /// Slides the UI node in from 'hidden_offset' logical pixels away
+    pub fn slide(hidden_offset: Vec2) -> Self {
+        Self {
+            hidden_offset,
+            ..Default::default()
+        }
+    }
+    pub fn with_durations(mut self, enter_duration: f32, exit_duration: f32) -> Self {
+        self.enter_duration = enter_duration;
+        self.exit_duration = exit_duration;
+        self
+    }
+    pub fn with_curves(
+        mut self,
+        enter_curve: impl Into<AnchorTransitionCurve>,
+        exit_curve: impl Into<AnchorTransitionCurve>,
+    ) -> Self {
+        self.enter_curve = enter_curve.into();
+        self.exit_curve = exit_curve.into();
+        self
+    }
+}
+
+/// The easing of an 'AnchorTransition', sampled with the progress of the animation from 0.0 to 1.0
+#[derive(Reflect, Clone)]
+#[reflect(opaque)]
+#[reflect(Clone, Debug, PartialEq)]
+pub enum AnchorTransitionCurve {
+    /// One of the easing functions of bevy
+    Ease(EaseFunction),
+    /// A user defined curve, clamped to its domain when sampled
+    Custom(Arc<dyn Curve<f32> + Send + Sync>),
+}
+
+impl AnchorTransitionCurve {
+    pub fn custom(curve: impl Curve<f32> + Send + Sync + 'static) -> Self {
+        Self::Custom(Arc::new(curve))
+    }
+
+    /// How far along the animation is at 'progress'
+    pub fn sample(&self, progress: f32) -> f32 {
+        match self {
+            AnchorTransitionCurve::Ease(ease) => ease.sample_clamped(progress),
+            AnchorTransitionCurve::Custom(curve) => curve.sample_clamped(progress),
+        }
+    }
+}
+
+impl From<EaseFunction> for AnchorTransitionCurve {
+    fn from(ease: EaseFunction) -> Self {
+        Self::Ease(ease)
+    }
+}
+
+impl std::fmt::Debug for AnchorTransitionCurve {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AnchorTransitionCurve::Ease(ease) => f.debug_tuple("Ease").field(ease).finish(),
+            AnchorTransitionCurve::Custom(_) => f.debug_tuple("Custom").finish_non_exhaustive(),
+        }
+    }
+}
+
+/// Custom curves are only equal when they are the same instance
+impl PartialEq for AnchorTransitionCurve {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (AnchorTransitionCurve::Ease(a), AnchorTransitionCurve::Ease(b)) => a == b,
+            (AnchorTransitionCurve::Custom(a), AnchorTransitionCurve::Custom(b)) => {
+                Arc::ptr_eq(a, b)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Where in its enter and exit animation an anchored UI node is
+#[derive(Default, Reflect, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnchorAnimationPhase {
+    Entering,
+    #[default]
+    Visible,
+    Exiting,
+    Hidden,
+}
+
+/// The animation state of an anchored UI node, advanced by the plugin from the same decision that sets its 'Visibility'
+#[derive(Component, Reflect, Debug, Clone, Copy, PartialEq, Default)]
+pub struct AnchorUiAnimation {
+    pub phase: AnchorAnimationPhase,
+    /// How far from 0.0 to 1.0 into 'AnchorAnimationPhase::Entering' or 'AnchorAnimationPhase::Exiting' the UI node is
+    pub progress: f32,
+}
+
+impl AnchorUiAnimation {
+    /// Advances the animation over 'dt' seconds, returns if the UI node is still visible
+    pub(crate) fn step(&mut self, transition: &AnchorTransition, hidden: bool, dt: f32) -> bool {
+        use AnchorAnimationPhase::*;
+        match (self.phase, hidden) {
+            (Hidden, false) => *self = Self::new(Entering, 0.0),
+            (Visible, true) => *self = Self::new(Exiting, 0.0),
+            // turn around halfway, continuing from the same point
+            (Entering, true) => *self = Self::new(Exiting, 1.0 - self.progress),
+            (Exiting, false) => *self = Self::new(Entering, 1.0 - self.progress),
+            _ => {}
+        }
+        let rate = |duration: f32| {
+            if duration <= 0.0 {
+                f32::INFINITY
+            } else {
+                dt / duration
+            }
+        };
+        match self.phase {
+            Entering => {
+                self.progress += rate(transition.enter_duration);
+                if self.progress >= 1.0 {
+                    *self = Self::new(Visible, 0.0);
+                }
+            }
+            Exiting => {
+                self.progress += rate(transition.exit_duration);
+                if self.progress >= 1.0 {
+                    *self = Self::new(Hidden, 0.0);
+                }
+            }
+            Visible | Hidden => {}
+        }
+        self.phase != Hidden
+    }
+
+    /// Sets the phase directly, for UI nodes without an 'AnchorTransition'
+    pub(crate) fn set_visible(&mut self, visible: bool) {
+        let phase = if visible {
+            AnchorAnimationPhase::Visible
+        } else {
+            AnchorAnimationPhase::Hidden
+        };
+        if self.phase != phase {
+            *self = Self::new(phase, 0.0);
+        }
+    }
+
+    fn new(phase: AnchorAnimationPhase, progress: f32) -> Self {
+        Self { phase, progress }
+    }
+
+    /// How visible the UI node is from 0.0 (hidden) to 1.0 (visible), after the curves are applied
+    pub fn visible_amount(&self, transition: &AnchorTransition) -> f32 {
+        let progress = self.progress.clamp(0.0, 1.0);
+        match self.phase {
+            AnchorAnimationPhase::Entering => transition.enter_curve.sample(progress),
+            AnchorAnimationPhase::Exiting => 1.0 - transition.exit_curve.sample(progress),
+            AnchorAnimationPhase::Visible => 1.0,
+            AnchorAnimationPhase::Hidden => 0.0,
+        }
+    }
+}
+
+/// Writes the scale from 'AnchorUiConfig::distance_scaling' or 'AnchorUiConfig::world_size' and the 'AnchorTransition' into the 'UiTransform' of the UI node
+///
+/// UI nodes that use neither are left alone, so their 'UiTransform' can be used for other things
+pub(crate) fn write_ui_transform(
+    ui_transform: &mut Mut<UiTransform>,
+    animation: &AnchorUiAnimation,
+    scale: Vec2,
+    config: &AnchorUiConfig,
+) {
+    if config.transition.is_none()
+        && config.distance_scaling.is_none()
+        && config.world_size.is_none()
+    {
+        return;
+    }
+    let mut scale = scale;
+    let mut translation = Val2::ZERO;
+    if let Some(transition) = &config.transition {
+        let amount = animation.visible_amount(transition);
+        scale *= transition.hidden_scale.lerp(1.0, amount);
+        let offset = transition.hidden_offset * (1.0 - amount);
+        translation = Val2::px(offset.x, offset.y);
+    }
+    if ui_transform.scale != scale {
+        ui_transform.scale = scale;
+    }
+    if ui_transform.translation != translation {
+        ui_transform.translation = translation;
+    }
+}
+
+/// Updates the 'UiTransform' once the 'AnchorTransition' has been advanced for this frame
+///
+/// The scale without the animation is already written when the UI nodes are moved, before they are laid out
+pub(crate) fn system_apply_ui_transform(
+    uinodes: Query<(
+        &mut UiTransform,
+        &AnchorUiAnimation,
+        &AnchorUiProjection,
+        &AnchorUiConfig,
+    )>,
+) {
+    for (mut ui_transform, animation, projection, config) in uinodes {
+        if config.transition.is_some() {
+            write_ui_transform(&mut ui_transform, animation, projection.scale, config);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transition() -> AnchorTransition {
+        AnchorTransition::default().with_durations(1.0, 1.0)
+    }
+
+    #[test]
+    fn step_exits_and_enters_over_the_durations() {
+        let mut animation = AnchorUiAnimation::default();
+        assert!(animation.step(&transition(), true, 0.5));
+        assert_eq!(animation.phase, AnchorAnimationPhase::Exiting);
+        assert_eq!(animation.progress, 0.5);
+        assert!(!animation.step(&transition(), true, 0.6));
+        assert_eq!(animation.phase, AnchorAnimationPhase::Hidden);
+
+        assert!(animation.step(&transition(), false, 0.25));
+        assert_eq!(animation.phase, AnchorAnimationPhase::Entering);
+        assert!(animation.step(&transition(), false, 1.0));
+        assert_eq!(animation.phase, AnchorAnimationPhase::Visible);
+    }
+
+    #[test]
+    fn step_turns_around_from_the_same_point() {
+        let mut animation = AnchorUiAnimation::new(AnchorAnimationPhase::Entering, 0.25);
+        animation.step(&transition(), true, 0.0);
+        assert_eq!(animation.phase, AnchorAnimationPhase::Exiting);
+        assert_eq!(animation.progress, 0.75);
+    }
+
+    #[test]
+    fn step_without_duration_is_instant() {
+        let transition = transition().with_durations(0.0, 0.0);
+        let mut animation = AnchorUiAnimation::default();
+        assert!(!animation.step(&transition, true, 0.0));
+        assert_eq!(animation.phase, AnchorAnimationPhase::Hidden);
+    }
+
+    #[test]
+    fn custom_curve_is_sampled_clamped() {
+        let curve = AnchorTransitionCurve::custom(FunctionCurve::new(Interval::UNIT, |t| t * t));
+        assert_eq!(curve.sample(0.5), 0.25);
+        assert_eq!(curve.sample(2.0), 1.0);
+    }
+}
@@ -0,0 +1,393 @@
+use std::sync::Arc;
+
+use bevy::{
+    ecs::{
+        lifecycle::HookContext,
+        query::{QueryData, QuerySingleError},
+        world::DeferredWorld,
+    },
+    platform::collections::{HashMap, HashSet},
+    prelude::*,
+    window::PrimaryWindow,
+};
+
+use crate::{
+    sort_by_priority, AnchorPriority, AnchorUiConfig, AnchorUiNode, AnchorUiProjection,
+    AnchorUiVisibility,
+};
+
+/// Spawns the contents of a cluster badge, called once on the badge entity when it is spawned
+pub type AnchorClusterTemplate = Arc<dyn Fn(&mut EntityCommands) + Send + Sync>;
+
+/// Defines a group of anchored UI nodes that are clustered together when they are close to each other on screen
+///
+/// Anchored UI nodes join the group with 'InAnchorCluster', nodes of the same group that are projected within
+/// 'radius' of each other are hidden and replaced by a single badge, placed at their centroid
+#[derive(Component, Clone)]
+#[component(on_remove = despawn_cluster_badges)]
+pub struct AnchorClusterGroup {
+    /// Distance in logical pixels between projected positions for them to be clustered
+    pub radius: f32,
+    /// The least number of anchored UI nodes that are replaced by a badge
+    pub min_members: usize,
+    /// Spawns the contents of the badge, see 'AnchorUiCluster' and 'AnchorUiClusterCount'
+    pub template: AnchorClusterTemplate,
+    /// The badges currently spawned for this group
+    badges: Vec<Entity>,
+}
+
+impl AnchorClusterGroup {
+    pub fn new(
+        radius: f32,
+        template: impl Fn(&mut EntityCommands) + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            radius,
+            min_members: 2,
+            template: Arc::new(template),
+            badges: Vec::new(),
+        }
+    }
+    pub fn with_min_members(mut self, min_members: usize) -> Self {
+        self.min_members = min_members;
+        self
+    }
+}
+
+/// Despawns the badges of an 'AnchorClusterGroup' when it is removed, or its entity despawned
+///
+/// Its members are shown again by 'system_release_cluster_members'
+fn despawn_cluster_badges(mut world: DeferredWorld, context: HookContext) {
+    let Some(mut group) = world.get_mut::<AnchorClusterGroup>(context.entity) else {
+        return;
+    };
+    let badges = std::mem::take(&mut group.badges);
+    let mut commands = world.commands();
+    for badge in badges {
+        commands.entity(badge).try_despawn();
+    }
+}
+
+/// Relationship that puts an anchored UI node into the 'AnchorClusterGroup' of the given entity
+#[derive(Component, Reflect, Clone, Debug, PartialEq)]
+#[relationship(relationship_target = AnchorClusterMembers)]
+pub struct InAnchorCluster(pub Entity);
+
+/// relationship that defines which anchored uinodes are part of this 'AnchorClusterGroup'
+#[derive(Component, Reflect, Clone, Debug, PartialEq)]
+#[relationship_target(relationship = InAnchorCluster)]
+pub struct AnchorClusterMembers(Vec<Entity>);
+
+/// Component on the badge UI node the plugin spawns for a cluster
+#[derive(Component, Reflect, Clone, Debug, PartialEq)]
+pub struct AnchorUiCluster {
+    /// The 'AnchorClusterGroup' this badge belongs to
+    pub group: Entity,
+    /// The anchored UI nodes that are hidden and replaced by this badge
+    pub members: Vec<Entity>,
+}
+
+impl AnchorUiCluster {
+    /// The number of anchored UI nodes in this cluster
+    pub fn count(&self) -> usize {
+        self.members.len()
+    }
+}
+
+/// Marks a 'Text' below a cluster badge, that the plugin will keep updated with the number of clustered UI nodes
+#[derive(Component, Reflect, Clone, Copy, Debug, Default, PartialEq)]
+pub struct AnchorUiClusterCount;
+
+/// What the clustering needs from an anchored UI node
+#[derive(QueryData)]
+#[query_data(mutable)]
+pub(crate) struct ClusterQuery {
+    entity: Entity,
+    node: &'static Node,
+    visibility: &'static mut AnchorUiVisibility,
+    projection: &'static AnchorUiProjection,
+    config: &'static AnchorUiConfig,
+    priority: Option<&'static AnchorPriority>,
+}
+
+/// A cluster found this frame
+struct FoundCluster {
+    centroid: Vec2,
+    members: Vec<Entity>,
+}
+
+/// Greedily gathers the nodes within 'radius' of the highest ranked unclustered node, until all are assigned
+fn find_clusters(
+    candidates: &[(Entity, AnchorPriority, f32)],
+    positions: &HashMap<Entity, Vec2>,
+    radius: f32,
+    min_members: usize,
+) -> Vec<FoundCluster> {
+    // a tiny radius would put every node in its own cell, far beyond what 'IVec2' can hold
+    let radius = radius.max(1.0);
+    let cell_of = |position: Vec2| (position / radius).floor().as_ivec2();
+
+    let mut grid: HashMap<IVec2, Vec<Entity>> = HashMap::default();
+    for (entity, _, _) in candidates {
+        grid.entry(cell_of(positions[entity]))
+            .or_default()
+            .push(*entity);
+    }
+
+    let mut assigned: HashSet<Entity> = HashSet::default();
+    let mut clusters = Vec::new();
+    for (seed, _, _) in candidates {
+        if assigned.contains(seed) {
+            continue;
+        }
+        let center = positions[seed];
+        let cell = cell_of(center);
+        let mut members = vec![*seed];
+        for y in -1..=1 {
+            for x in -1..=1 {
+                let Some(entities) = grid.get(&(cell + IVec2::new(x, y))) else {
+                    continue;
+                };
+                members.extend(entities.iter().filter(|entity| {
+                    *entity != seed
+                        && !assigned.contains(*entity)
+                        && positions[*entity].distance(center) <= radius
+                }));
+            }
+        }
+        if members.len() < min_members.max(2) {
+            continue;
+        }
+        members.sort_unstable();
+        for member in &members {
+            assigned.insert(*member);
+        }
+        let centroid =
+            members.iter().map(|member| positions[member]).sum::<Vec2>() / members.len() as f32;
+        clusters.push(FoundCluster { centroid, members });
+    }
+    clusters
+}
+
+pub(crate) fn system_cluster_ui_nodes(
+    mut commands: Commands,
+    window: Query<&Window, With<PrimaryWindow>>,
+    mut groups: Query<(
+        Entity,
+        &mut AnchorClusterGroup,
+        Option<&AnchorClusterMembers>,
+    )>,
+    mut uinodes: Query<ClusterQuery, With<AnchorUiNode>>,
+    mut badges: Query<
+        (
+            &mut Node,
+            &mut Visibility,
+            &ComputedNode,
+            &mut AnchorUiCluster,
+        ),
+        Without<AnchorUiNode>,
+    >,
+) {
+    let window_height = match window.single() {
+        Ok(window) => window.height(),
+        Err(QuerySingleError::NoEntities(_)) => return,
+        Err(err @ QuerySingleError::MultipleEntities(_)) => {
+            bevy::log::error!("more than one primary window: {err}");
+            return;
+        }
+    };
+
+    for (group_entity, mut group, members) in groups.iter_mut() {
+        let mut candidates = Vec::new();
+        let mut positions = HashMap::default();
+        for member in members.into_iter().flat_map(|members| members.iter()) {
+            let Ok(mut uinode) = uinodes.get_mut(member) else {
+                continue;
+            };
+            if uinode.node.display == Display::None || uinode.visibility.hidden_by_target() {
+                uinode.visibility.clustered = None;
+                continue;
+            }
+            uinode.visibility.clustered = Some(false);
+            candidates.push((
+                uinode.entity,
+                uinode.priority.copied().unwrap_or_default(),
+                uinode.projection.depth,
+            ));
+            positions.insert(uinode.entity, uinode.projection.position);
+        }
+        sort_by_priority(&mut candidates);
+
+        let clusters = find_clusters(&candidates, &positions, group.radius, group.min_members);
+
+        // dissolve the badges that are no longer needed
+        let needed = clusters.len().min(group.badges.len());
+        for badge in group.badges.split_off(needed) {
+            commands.entity(badge).try_despawn();
+        }
+
+        for (index, cluster) in clusters.into_iter().enumerate() {
+            for member in &cluster.members {
+                if let Ok(mut uinode) = uinodes.get_mut(*member) {
+                    uinode.visibility.clustered = Some(true);
+                }
+            }
+
+            let Some(badge) = group.badges.get(index).copied() else {
+                let mut badge = commands.spawn((
+                    Node {
+                        position_type: PositionType::Absolute,
+                        left: Val::Px(cluster.centroid.x),
+                        bottom: Val::Px(window_height - cluster.centroid.y),
+                        ..Default::default()
+                    },
+                    // its size is only known once it has been laid out, so it can not be centred yet
+                    Visibility::Hidden,
+                    AnchorUiCluster {
+                        group: group_entity,
+                        members: cluster.members,
+                    },
+                ));
+                (group.template)(&mut badge);
+                group.badges.push(badge.id());
+                continue;
+            };
+
+            let Ok((mut node, mut visibility, computed_node, mut info)) = badges.get_mut(badge)
+            else {
+                continue;
+            };
+            let size = computed_node.size() * computed_node.inverse_scale_factor();
+            if node.position_type != PositionType::Absolute {
+                node.position_type = PositionType::Absolute;
+            }
+            node.left = Val::Px(cluster.centroid.x - size.x / 2.0);
+            node.bottom = Val::Px(window_height - cluster.centroid.y - size.y / 2.0);
+            visibility.set_if_neq(Visibility::Inherited);
+            if info.members != cluster.members {
+                info.members = cluster.members;
+            }
+        }
+    }
+}
+
+/// Shows the anchored UI nodes that no longer belong to a group, or whose group was removed,
+/// runs even when no group is left
+pub(crate) fn system_release_cluster_members(
+    groups: Query<(), With<AnchorClusterGroup>>,
+    uinodes: Query<(&mut AnchorUiVisibility, Option<&InAnchorCluster>), With<AnchorUiNode>>,
+) {
+    for (mut visibility, cluster) in uinodes {
+        let in_group = cluster.is_some_and(|cluster| groups.contains(cluster.0));
+        if !in_group && visibility.clustered.is_some() {
+            visibility.clustered = None;
+        }
+    }
+}
+
+pub(crate) fn system_update_cluster_counts(
+    badges: Query<(Entity, &AnchorUiCluster), Changed<AnchorUiCluster>>,
+    children: Query<&Children>,
+    mut counts: Query<&mut Text, With<AnchorUiClusterCount>>,
+) {
+    for (badge, cluster) in badges.iter() {
+        for descendant in std::iter::once(badge).chain(children.iter_descendants(badge)) {
+            if let Ok(mut text) = counts.get_mut(descendant) {
+                text.0 = cluster.count().to_string();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{spawn_anchored, test_app, visibility};
+
+    fn candidates(count: u32) -> Vec<(Entity, AnchorPriority, f32)> {
+        (1..=count)
+            .map(|index| {
+                let entity = Entity::from_raw_u32(index).unwrap();
+                (entity, AnchorPriority::default(), 0.0)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn find_clusters_gathers_nearby_nodes() {
+        let candidates = candidates(3);
+        let positions: HashMap<Entity, Vec2> = candidates
+            .iter()
+            .zip([
+                Vec2::new(0.0, 0.0),
+                Vec2::new(10.0, 0.0),
+                Vec2::new(100.0, 0.0),
+            ])
+            .map(|((entity, _, _), position)| (*entity, position))
+            .collect();
+
+        let clusters = find_clusters(&candidates, &positions, 20.0, 2);
+        assert_eq!(clusters.len(), 1);
+        let mut expected = vec![candidates[0].0, candidates[1].0];
+        expected.sort_unstable();
+        assert_eq!(clusters[0].members, expected);
+        assert_eq!(clusters[0].centroid, Vec2::new(5.0, 0.0));
+
+        // not enough members close together
+        assert!(find_clusters(&candidates, &positions, 20.0, 3).is_empty());
+    }
+
+    #[test]
+    fn find_clusters_with_a_zero_radius() {
+        let candidates = candidates(2);
+        let positions: HashMap<Entity, Vec2> = candidates
+            .iter()
+            .map(|(entity, _, _)| (*entity, Vec2::splat(1e9)))
+            .collect();
+        // nodes on the same spot still cluster, and far off positions do not overflow the grid
+        let clusters = find_clusters(&candidates, &positions, 0.0, 2);
+        assert_eq!(clusters.len(), 1);
+    }
+
+    #[test]
+    fn removing_group_shows_members_again() {
+        let mut app = test_app();
+        app.world_mut().spawn((Window::default(), PrimaryWindow));
+        let group = app
+            .world_mut()
+            .spawn(AnchorClusterGroup::new(50.0, |_| {}))
+            .id();
+        let members = [Vec2::new(100.0, 100.0), Vec2::new(110.0, 100.0)].map(|position| {
+            let projection = AnchorUiProjection {
+                position,
+                ..Default::default()
+            };
+            spawn_anchored(&mut app, (projection, InAnchorCluster(group)))
+        });
+        app.update();
+        for member in members {
+            assert_eq!(visibility(&app, member), Visibility::Hidden);
+        }
+        // the new badge waits to be laid out before it is shown centred
+        let (badge, _) = app
+            .world_mut()
+            .query::<(Entity, &AnchorUiCluster)>()
+            .single(app.world())
+            .unwrap();
+        assert_eq!(visibility(&app, badge), Visibility::Hidden);
+
+        app.world_mut()
+            .entity_mut(group)
+            .remove::<AnchorClusterGroup>();
+        app.update();
+        for member in members {
+            assert_eq!(visibility(&app, member), Visibility::Visible);
+        }
+        assert!(app
+            .world_mut()
+            .query::<&AnchorUiCluster>()
+            .iter(app.world())
+            .next()
+            .is_none());
+    }
+}
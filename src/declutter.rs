@@ -0,0 +1,333 @@
+use bevy::{ecs::query::QueryData, platform::collections::HashMap, prelude::*};
+
+use crate::{
+    move_placed_node, sort_by_priority, AnchorPriority, AnchorUiConfig, AnchorUiProjection,
+    AnchorUiVisibility,
+};
+
+/// Size in logical pixels of the grid cells used to find overlapping UI nodes
+const GRID_CELL_SIZE: f32 = 64.0;
+
+/// How many times a UI node is moved out of the way of others before giving up on it
+const MAX_RESOLVE_STEPS: usize = 16;
+
+/// How overlapping anchored UI nodes are resolved
+#[derive(Reflect, Debug, Clone, Copy, PartialEq, Default)]
+pub enum DeclutterMode {
+    /// The UI node with the lower priority is hidden
+    #[default]
+    Hide,
+    /// The UI node with the lower priority is pushed out of the way, along the axis where it overlaps the least
+    Nudge,
+    /// The UI node with the lower priority is moved up, on top of the ones it overlaps
+    Stack,
+}
+
+/// Resource that enables the declutter pass, which stops anchored UI nodes from being drawn on top of each other
+///
+/// UI nodes are placed in order of their 'AnchorPriority', then by how close they are to the camera,
+/// and nodes that would overlap an already placed one are moved or hidden according to 'mode'
+#[derive(Resource, Reflect, Debug, Clone, PartialEq)]
+#[reflect(Resource)]
+pub struct AnchorUiDeclutter {
+    /// How overlapping UI nodes are resolved
+    pub mode: DeclutterMode,
+    /// Minimum space in logical pixels between two UI nodes
+    pub spacing: f32,
+    /// How far in logical pixels a UI node may be moved by 'DeclutterMode::Nudge' or 'DeclutterMode::Stack',
+    /// UI nodes that would need to move further are hidden instead
+    pub max_displacement: f32,
+}
+
+impl Default for AnchorUiDeclutter {
+    fn default() -> Self {
+        Self {
+            mode: DeclutterMode::Hide,
+            spacing: 0.0,
+            max_displacement: 100.0,
+        }
+    }
+}
+
+impl AnchorUiDeclutter {
+    pub fn new(mode: DeclutterMode) -> Self {
+        Self {
+            mode,
+            ..Default::default()
+        }
+    }
+    pub fn with_spacing(mut self, spacing: f32) -> Self {
+        self.spacing = spacing;
+        self
+    }
+    pub fn with_max_displacement(mut self, max_displacement: f32) -> Self {
+        self.max_displacement = max_displacement;
+        self
+    }
+}
+
+/// Already placed rects, bucketed into a grid so that only nearby rects have to be tested for overlap
+#[derive(Default)]
+struct PlacedRects {
+    rects: Vec<Rect>,
+    cells: HashMap<IVec2, Vec<usize>>,
+}
+
+impl PlacedRects {
+    fn cells_of(rect: Rect) -> impl Iterator<Item = IVec2> {
+        let min = (rect.min / GRID_CELL_SIZE).floor().as_ivec2();
+        let max = (rect.max / GRID_CELL_SIZE).floor().as_ivec2();
+        (min.y..=max.y).flat_map(move |y| (min.x..=max.x).map(move |x| IVec2::new(x, y)))
+    }
+
+    /// The first placed rect that overlaps 'rect', if any
+    fn overlapping(&self, rect: Rect) -> Option<Rect> {
+        Self::cells_of(rect)
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+            .map(|index| self.rects[*index])
+            .find(|placed| overlaps(*placed, rect))
+    }
+
+    fn insert(&mut self, rect: Rect) {
+        let index = self.rects.len();
+        self.rects.push(rect);
+        for cell in Self::cells_of(rect) {
+            self.cells.entry(cell).or_default().push(index);
+        }
+    }
+}
+
+fn overlaps(a: Rect, b: Rect) -> bool {
+    a.min.x < b.max.x && b.min.x < a.max.x && a.min.y < b.max.y && b.min.y < a.max.y
+}
+
+/// Moves 'rect' until it no longer overlaps any placed rect, returns 'None' if it would have to move too far
+fn resolve(settings: &AnchorUiDeclutter, placed: &PlacedRects, rect: Rect) -> Option<Rect> {
+    let mut resolved = rect;
+    for _ in 0..MAX_RESOLVE_STEPS {
+        let Some(other) = placed.overlapping(resolved) else {
+            return Some(resolved);
+        };
+        let offset = match settings.mode {
+            DeclutterMode::Hide => return None,
+            DeclutterMode::Stack => Vec2::new(0.0, other.min.y - resolved.max.y),
+            DeclutterMode::Nudge => {
+                let overlap = resolved.intersect(other).size();
+                let away = (resolved.center() - other.center()).signum();
+                if overlap.x < overlap.y {
+                    let x = if away.x < 0.0 {
+                        other.min.x - resolved.max.x
+                    } else {
+                        other.max.x - resolved.min.x
+                    };
+                    Vec2::new(x, 0.0)
+                } else {
+                    let y = if away.y < 0.0 {
+                        other.min.y - resolved.max.y
+                    } else {
+                        other.max.y - resolved.min.y
+                    };
+                    Vec2::new(0.0, y)
+                }
+            }
+        };
+        resolved = Rect::from_corners(resolved.min + offset, resolved.max + offset);
+        if resolved.min.distance(rect.min) > settings.max_displacement {
+            return None;
+        }
+    }
+    None
+}
+
+/// Everything the declutter pass reads and writes on an anchored UI node
+#[derive(QueryData)]
+#[query_data(mutable)]
+pub(crate) struct DeclutterQuery {
+    entity: Entity,
+    node: &'static mut Node,
+    visibility: &'static mut AnchorUiVisibility,
+    projection: &'static mut AnchorUiProjection,
+    config: &'static AnchorUiConfig,
+    priority: Option<&'static AnchorPriority>,
+}
+
+pub(crate) fn system_declutter_ui_nodes(
+    settings: Res<AnchorUiDeclutter>,
+    mut uinodes: Query<DeclutterQuery>,
+) {
+    let mut candidates = Vec::new();
+    for mut uinode in uinodes.iter_mut() {
+        if uinode.node.display == Display::None
+            || uinode.visibility.hidden_by_target()
+            || uinode.visibility.clustered == Some(true)
+            || uinode.visibility.over_budget == Some(true)
+        {
+            uinode.visibility.decluttered = None;
+            continue;
+        }
+        candidates.push((
+            uinode.entity,
+            uinode.priority.copied().unwrap_or_default(),
+            uinode.projection.depth,
+        ));
+    }
+    sort_by_priority(&mut candidates);
+
+    let margin = settings.spacing.max(0.0) / 2.0;
+    let mut placed = PlacedRects::default();
+    for (entity, _, _) in candidates {
+        let Ok(DeclutterQueryItem {
+            mut node,
+            visibility: mut vis,
+            mut projection,
+            ..
+        }) = uinodes.get_mut(entity)
+        else {
+            continue;
+        };
+        let rect = projection.rect.inflate(margin);
+        let Some(resolved) = resolve(&settings, &placed, rect) else {
+            vis.decluttered = Some(true);
+            continue;
+        };
+        vis.decluttered = Some(false);
+        placed.insert(resolved);
+
+        let offset = resolved.min - rect.min;
+        if offset != Vec2::ZERO {
+            move_placed_node(node.bypass_change_detection(), &mut projection, offset);
+        }
+    }
+}
+
+/// Shows the UI nodes that the declutter pass hid, once 'AnchorUiDeclutter' has been removed
+pub(crate) fn system_reset_declutter(uinodes: Query<&mut AnchorUiVisibility>) {
+    for mut visibility in uinodes {
+        if visibility.decluttered.is_some() {
+            visibility.decluttered = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn placed(rects: &[Rect]) -> PlacedRects {
+        let mut placed = PlacedRects::default();
+        for rect in rects {
+            placed.insert(*rect);
+        }
+        placed
+    }
+
+    #[test]
+    fn removing_declutter_shows_nodes_again() {
+        use crate::tests::{spawn_anchored, test_app, visibility};
+
+        let mut app = test_app();
+        app.insert_resource(AnchorUiDeclutter::new(DeclutterMode::Hide));
+        let projection = AnchorUiProjection {
+            rect: Rect::new(0.0, 0.0, 10.0, 10.0),
+            ..Default::default()
+        };
+        let first = spawn_anchored(&mut app, projection);
+        let second = spawn_anchored(&mut app, projection);
+        app.update();
+        let hidden: Vec<Entity> = [first, second]
+            .into_iter()
+            .filter(|uinode| visibility(&app, *uinode) == Visibility::Hidden)
+            .collect();
+        assert_eq!(hidden.len(), 1);
+
+        app.world_mut().remove_resource::<AnchorUiDeclutter>();
+        app.update();
+        assert_eq!(visibility(&app, hidden[0]), Visibility::Visible);
+    }
+
+    #[test]
+    fn placed_rects_finds_overlap_across_cells() {
+        let placed = placed(&[Rect::new(60.0, 60.0, 140.0, 80.0)]);
+        assert!(placed
+            .overlapping(Rect::new(130.0, 70.0, 150.0, 90.0))
+            .is_some());
+        assert!(placed
+            .overlapping(Rect::new(-10.0, 60.0, 40.0, 80.0))
+            .is_none());
+        // touching edges do not overlap
+        assert!(placed
+            .overlapping(Rect::new(140.0, 60.0, 160.0, 80.0))
+            .is_none());
+    }
+
+    #[test]
+    fn resolve_keeps_free_rect() {
+        let settings = AnchorUiDeclutter::new(DeclutterMode::Hide);
+        let placed = placed(&[Rect::new(0.0, 0.0, 10.0, 10.0)]);
+        let rect = Rect::new(20.0, 0.0, 30.0, 10.0);
+        assert_eq!(resolve(&settings, &placed, rect), Some(rect));
+    }
+
+    #[test]
+    fn resolve_hide_rejects_overlap() {
+        let settings = AnchorUiDeclutter::new(DeclutterMode::Hide);
+        let placed = placed(&[Rect::new(0.0, 0.0, 10.0, 10.0)]);
+        assert_eq!(
+            resolve(&settings, &placed, Rect::new(5.0, 5.0, 15.0, 15.0)),
+            None
+        );
+    }
+
+    #[test]
+    fn resolve_nudge_moves_along_least_overlap() {
+        let settings = AnchorUiDeclutter::new(DeclutterMode::Nudge);
+        let placed = placed(&[Rect::new(0.0, 0.0, 20.0, 20.0)]);
+        // overlaps 2 pixels horizontally and 16 vertically, so it is pushed right
+        let resolved = resolve(&settings, &placed, Rect::new(18.0, 2.0, 28.0, 18.0));
+        assert_eq!(resolved, Some(Rect::new(20.0, 2.0, 30.0, 18.0)));
+    }
+
+    #[test]
+    fn resolve_stack_moves_above() {
+        let settings = AnchorUiDeclutter::new(DeclutterMode::Stack);
+        let placed = placed(&[Rect::new(0.0, 50.0, 20.0, 60.0)]);
+        let resolved = resolve(&settings, &placed, Rect::new(5.0, 55.0, 25.0, 65.0));
+        assert_eq!(resolved, Some(Rect::new(5.0, 40.0, 25.0, 50.0)));
+    }
+
+    #[test]
+    fn resolve_gives_up_past_max_displacement() {
+        let settings = AnchorUiDeclutter::new(DeclutterMode::Stack).with_max_displacement(5.0);
+        let placed = placed(&[Rect::new(0.0, 50.0, 20.0, 60.0)]);
+        assert_eq!(
+            resolve(&settings, &placed, Rect::new(5.0, 55.0, 25.0, 65.0)),
+            None
+        );
+    }
+
+    #[test]
+    fn resolve_thousand_labels_deterministically() {
+        let settings = AnchorUiDeclutter::new(DeclutterMode::Nudge);
+        let rects: Vec<Rect> = (0..1000)
+            .map(|i| {
+                let position = Vec2::new((i * 37 % 1920) as f32, (i * 53 % 1080) as f32);
+                Rect::from_corners(position, position + Vec2::new(80.0, 20.0))
+            })
+            .collect();
+        let run = || {
+            let mut placed = PlacedRects::default();
+            let mut results = Vec::new();
+            for rect in &rects {
+                let resolved = resolve(&settings, &placed, *rect);
+                if let Some(resolved) = resolved {
+                    assert!(placed.overlapping(resolved).is_none());
+                    placed.insert(resolved);
+                }
+                results.push(resolved);
+            }
+            results
+        };
+        assert_eq!(run(), run());
+    }
+}
@@ -0,0 +1,130 @@
+use bevy::{ecs::query::QueryData, prelude::*};
+
+use crate::{AnchorUiNode, AnchorUiProjection, LeaderLineOf, LeaderLineSegments};
+
+/// Which component 'AnchorUiDepthSort' writes the draw order into
+#[derive(Default, Reflect, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DepthSortKind {
+    /// 'ZIndex', which only orders the anchored UI nodes among their siblings
+    #[default]
+    ZIndex,
+    /// 'GlobalZIndex', which orders the anchored UI nodes against all other UI
+    GlobalZIndex,
+}
+
+/// Resource that makes the plugin draw anchored UI nodes nearer to the camera on top of those further away
+///
+/// The furthest UI node gets 'base', and every nearer one the next value up. Leader lines are drawn
+/// right below the UI node they belong to, so they also go below the UI nodes nearer to the camera
+#[derive(Resource, Reflect, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[reflect(Resource)]
+pub struct AnchorUiDepthSort {
+    pub kind: DepthSortKind,
+    /// The lowest value that is written, use it to keep the anchored UI nodes below or above other UI, such as menus
+    pub base: i32,
+}
+
+impl AnchorUiDepthSort {
+    pub fn new(kind: DepthSortKind) -> Self {
+        Self { kind, base: 0 }
+    }
+    pub fn with_base(mut self, base: i32) -> Self {
+        self.base = base;
+        self
+    }
+}
+
+/// What the depth sorting needs from an anchored UI node
+#[derive(QueryData)]
+#[query_data(mutable)]
+pub(crate) struct DepthSortQuery {
+    entity: Entity,
+    node: &'static Node,
+    projection: &'static AnchorUiProjection,
+    z_index: &'static mut ZIndex,
+    global_z_index: Option<&'static mut GlobalZIndex>,
+    leader_line: Option<&'static LeaderLineSegments>,
+}
+
+/// The draw order of a leader line segment
+#[derive(QueryData)]
+#[query_data(mutable)]
+pub(crate) struct SegmentQuery {
+    entity: Entity,
+    z_index: &'static mut ZIndex,
+    global_z_index: Option<&'static mut GlobalZIndex>,
+}
+
+pub(crate) fn system_depth_sort_ui_nodes(
+    mut commands: Commands,
+    depth_sort: Res<AnchorUiDepthSort>,
+    mut uinodes: Query<DepthSortQuery, With<AnchorUiNode>>,
+    mut segments: Query<SegmentQuery, (With<LeaderLineOf>, Without<AnchorUiNode>)>,
+) {
+    let mut ordered: Vec<(Entity, f32)> = uinodes
+        .iter()
+        .filter(|uinode| uinode.node.display != Display::None)
+        .map(|uinode| (uinode.entity, uinode.projection.depth))
+        .collect();
+    // furthest first, and the entity to keep it stable between frames
+    ordered
+        .sort_unstable_by(|(a, a_depth), (b, b_depth)| b_depth.total_cmp(a_depth).then(a.cmp(b)));
+
+    for (rank, (entity, _)) in ordered.into_iter().enumerate() {
+        let Ok(mut uinode) = uinodes.get_mut(entity) else {
+            continue;
+        };
+        // every UI node gets two values, so its leader line fits right below it
+        let value = depth_sort.base.saturating_add(rank as i32 * 2 + 1);
+        set_z(
+            &mut commands,
+            depth_sort.kind,
+            entity,
+            value,
+            &mut uinode.z_index,
+            uinode
+                .global_z_index
+                .as_mut()
+                .map(|global| global.reborrow()),
+        );
+        for segment in uinode
+            .leader_line
+            .into_iter()
+            .flat_map(|lines| lines.iter())
+        {
+            if let Ok(mut segment) = segments.get_mut(segment) {
+                set_z(
+                    &mut commands,
+                    depth_sort.kind,
+                    segment.entity,
+                    value - 1,
+                    &mut segment.z_index,
+                    segment.global_z_index,
+                );
+            }
+        }
+    }
+}
+
+fn set_z(
+    commands: &mut Commands,
+    kind: DepthSortKind,
+    entity: Entity,
+    value: i32,
+    z_index: &mut Mut<ZIndex>,
+    global_z_index: Option<Mut<GlobalZIndex>>,
+) {
+    match kind {
+        DepthSortKind::ZIndex => {
+            z_index.set_if_neq(ZIndex(value));
+        }
+        DepthSortKind::GlobalZIndex => match global_z_index {
+            Some(mut global_z_index) => {
+                global_z_index.set_if_neq(GlobalZIndex(value));
+            }
+            None => {
+                commands.entity(entity).insert(GlobalZIndex(value));
+            }
+        },
+    }
+}
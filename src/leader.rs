@@ -0,0 +1,196 @@
+use bevy::{ecs::query::QuerySingleError, prelude::*, window::PrimaryWindow};
+
+use crate::AnchorUiProjection;
+
+/// Which edge of the anchored UI node the leader line starts at
+#[derive(Default, Reflect, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LeaderLineEdge {
+    /// The middle of the edge that is closest to the target
+    #[default]
+    Nearest,
+    Top,
+    Bottom,
+    Left,
+    Right,
+}
+
+/// How the leader line bends on its way to the target
+#[derive(Default, Reflect, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LeaderLineElbow {
+    /// A single straight line
+    #[default]
+    Straight,
+    /// Goes horizontally from the UI node, then vertically to the target
+    HorizontalFirst,
+    /// Goes vertically from the UI node, then horizontally to the target
+    VerticalFirst,
+}
+
+/// Draws a line from the anchored UI node to where its target is on screen, useful together with 'AnchorUiConfig::offset'
+///
+/// The line is made out of UI nodes spawned by the plugin, which are despawned together with the anchored UI node
+#[derive(Component, Reflect, Debug, Clone, PartialEq)]
+pub struct AnchorLeaderLine {
+    /// Which edge of the UI node the line starts at
+    pub edge: LeaderLineEdge,
+    /// How the line bends on its way to the target
+    pub elbow: LeaderLineElbow,
+    /// Thickness of the line in logical pixels
+    pub thickness: f32,
+    pub color: Color,
+}
+
+impl Default for AnchorLeaderLine {
+    fn default() -> Self {
+        Self {
+            edge: LeaderLineEdge::Nearest,
+            elbow: LeaderLineElbow::Straight,
+            thickness: 1.0,
+            color: Color::WHITE,
+        }
+    }
+}
+
+impl AnchorLeaderLine {
+    pub fn with_edge(mut self, edge: LeaderLineEdge) -> Self {
+        self.edge = edge;
+        self
+    }
+    pub fn with_elbow(mut self, elbow: LeaderLineElbow) -> Self {
+        self.elbow = elbow;
+        self
+    }
+    pub fn with_thickness(mut self, thickness: f32) -> Self {
+        self.thickness = thickness;
+        self
+    }
+    pub fn with_color(mut self, color: impl Into<Color>) -> Self {
+        self.color = color.into();
+        self
+    }
+
+    /// The points the line goes through, from the edge of 'rect' to 'target'
+    fn points(&self, rect: Rect, target: Vec2) -> Vec<Vec2> {
+        let center = rect.center();
+        let top = Vec2::new(center.x, rect.min.y);
+        let bottom = Vec2::new(center.x, rect.max.y);
+        let left = Vec2::new(rect.min.x, center.y);
+        let right = Vec2::new(rect.max.x, center.y);
+        let start = match self.edge {
+            LeaderLineEdge::Top => top,
+            LeaderLineEdge::Bottom => bottom,
+            LeaderLineEdge::Left => left,
+            LeaderLineEdge::Right => right,
+            LeaderLineEdge::Nearest => [top, bottom, left, right]
+                .into_iter()
+                .min_by(|a, b| {
+                    a.distance_squared(target)
+                        .total_cmp(&b.distance_squared(target))
+                })
+                .unwrap_or(center),
+        };
+        match self.elbow {
+            LeaderLineElbow::Straight => vec![start, target],
+            LeaderLineElbow::HorizontalFirst => vec![start, Vec2::new(target.x, start.y), target],
+            LeaderLineElbow::VerticalFirst => vec![start, Vec2::new(start.x, target.y), target],
+        }
+    }
+}
+
+/// Relationship on a leader line segment, pointing to the anchored UI node it belongs to
+#[derive(Component, Reflect, Clone, Debug, PartialEq)]
+#[relationship(relationship_target = LeaderLineSegments)]
+pub struct LeaderLineOf(pub Entity);
+
+/// relationship that defines which leader line segments belong to this anchored UI node
+#[derive(Component, Reflect, Clone, Debug, PartialEq)]
+#[relationship_target(relationship = LeaderLineOf, linked_spawn)]
+pub struct LeaderLineSegments(Vec<Entity>);
+
+pub(crate) fn system_update_leader_lines(
+    mut commands: Commands,
+    window: Query<&Window, With<PrimaryWindow>>,
+    lines: Query<(
+        Entity,
+        Ref<AnchorLeaderLine>,
+        &AnchorUiProjection,
+        Option<&LeaderLineSegments>,
+    )>,
+    orphaned: Query<&LeaderLineSegments, Without<AnchorLeaderLine>>,
+    mut segments: Query<(&mut Node, &mut UiTransform, &mut BackgroundColor), With<LeaderLineOf>>,
+) {
+    let window_height = match window.single() {
+        Ok(window) => window.height(),
+        Err(QuerySingleError::NoEntities(_)) => return,
+        Err(err @ QuerySingleError::MultipleEntities(_)) => {
+            bevy::log::error!("more than one primary window: {err}");
+            return;
+        }
+    };
+
+    // the leader line was removed from the anchored UI node
+    for segment in orphaned.iter().flat_map(|segments| segments.iter()) {
+        commands.entity(segment).try_despawn();
+    }
+
+    for (entity, line, projection, existing) in lines.iter() {
+        let points = line.points(projection.rect, projection.target_position);
+        let existing: Vec<Entity> = existing.into_iter().flat_map(|s| s.iter()).collect();
+
+        for (index, ends) in points.windows(2).enumerate() {
+            let delta = ends[1] - ends[0];
+            let center = (ends[0] + ends[1]) / 2.0;
+            // extended by the thickness, so that the segments of an elbow meet at the corner
+            let length = delta.length() + line.thickness;
+            let segment_node = Node {
+                position_type: PositionType::Absolute,
+                left: Val::Px(center.x - length / 2.0),
+                bottom: Val::Px(window_height - center.y - line.thickness / 2.0),
+                width: Val::Px(length),
+                height: Val::Px(line.thickness),
+                ..Default::default()
+            };
+            let transform = UiTransform::from_rotation(Rot2::radians(delta.y.atan2(delta.x)));
+
+            let Some(segment) = existing.get(index) else {
+                commands.spawn((
+                    segment_node,
+                    transform,
+                    BackgroundColor(line.color),
+                    // draw below the UI nodes
+                    ZIndex(-1),
+                    LeaderLineOf(entity),
+                ));
+                continue;
+            };
+            let Ok((mut node, mut ui_transform, mut background)) = segments.get_mut(*segment)
+            else {
+                continue;
+            };
+            node.set_if_neq(segment_node);
+            ui_transform.set_if_neq(transform);
+            // only when the line changed, the alpha is also dimmed along with the UI node
+            if line.is_changed() {
+                background.set_if_neq(BackgroundColor(line.color));
+            }
+        }
+
+        // the elbow style changed and fewer segments are needed
+        for segment in existing.iter().skip(points.len().saturating_sub(1)) {
+            commands.entity(*segment).try_despawn();
+        }
+    }
+}
+
+pub(crate) fn system_leader_line_visibility(
+    uinodes: Query<(&Visibility, &LeaderLineSegments)>,
+    mut segments: Query<&mut Visibility, (With<LeaderLineOf>, Without<LeaderLineSegments>)>,
+) {
+    for (visibility, lines) in uinodes.iter() {
+        for segment in lines.iter() {
+            if let Ok(mut segment_visibility) = segments.get_mut(segment) {
+                segment_visibility.set_if_neq(*visibility);
+            }
+        }
+    }
+}
@@ -1,20 +1,86 @@
-use std::marker::PhantomData;
+use std::{collections::VecDeque, marker::PhantomData};
 
 use bevy::{
-    camera::visibility::VisibilitySystems, ecs::query::QuerySingleError, prelude::*, ui::UiSystems,
+    camera::{
+        primitives::Aabb,
+        visibility::{RenderLayers, VisibilitySystems, VisibleEntities},
+    },
+    ecs::{
+        query::{QueryData, QuerySingleError},
+        system::SystemParam,
+    },
+    platform::collections::HashSet,
+    prelude::*,
+    ui::UiSystems,
     window::PrimaryWindow,
 };
 
+mod animation;
+mod cluster;
+mod declutter;
+mod depth_sort;
+mod leader;
+mod occlusion;
+mod opacity;
+mod stack;
+mod visibility;
+
+pub use animation::{
+    AnchorAnimationPhase, AnchorTransition, AnchorTransitionCurve, AnchorUiAnimation,
+};
+pub use cluster::{
+    AnchorClusterGroup, AnchorClusterMembers, AnchorClusterTemplate, AnchorUiCluster,
+    AnchorUiClusterCount, InAnchorCluster,
+};
+pub use declutter::{AnchorUiDeclutter, DeclutterMode};
+pub use depth_sort::{AnchorUiDepthSort, DepthSortKind};
+pub use leader::{
+    AnchorLeaderLine, LeaderLineEdge, LeaderLineElbow, LeaderLineOf, LeaderLineSegments,
+};
+pub use occlusion::{
+    AabbOcclusion, AnchorOccluder, AnchorOcclusion, AnchorOcclusionProvider, AnchorUiOcclusion,
+    Occlusion, OcclusionMode, OcclusionRay,
+};
+pub use opacity::{AnchorDepthFog, AnchorFade, AnchorUiOpacity};
+pub use stack::{AnchorUiStack, AnchorUiStackOrder, StackDirection};
+pub use visibility::{
+    AnchorUiVisibilityOverride, AnchorUiVisibilityPolicy, AnchorVisibilityContext,
+    AnchorVisibilityHook, VisibilityRule,
+};
+
 pub mod prelude {
     pub use crate::{
-        AnchorPoint, AnchorUiConfig, AnchorUiNode, AnchorUiPlugin, AnchoredUiNodes,
-        HorizontalAnchor, VerticalAnchor,
+        AabbOcclusion, ActiveAnchorPoint, AnchorAnimationPhase, AnchorClusterGroup, AnchorDepthFog,
+        AnchorDistanceCulling, AnchorDistanceScaling, AnchorFade, AnchorFallback, AnchorLeaderLine,
+        AnchorOccluder, AnchorOcclusion, AnchorOcclusionProvider, AnchorPoint, AnchorPrediction,
+        AnchorPriority, AnchorSmoothing, AnchorTransition, AnchorTransitionCurve,
+        AnchorUiAnimation, AnchorUiBudget, AnchorUiCluster, AnchorUiClusterCount,
+        AnchorUiCommandsExt, AnchorUiConfig, AnchorUiContainer, AnchorUiContainerMode,
+        AnchorUiDeclutter, AnchorUiDepthSort, AnchorUiNode, AnchorUiOcclusion, AnchorUiOpacity,
+        AnchorUiPlugin, AnchorUiProjection, AnchorUiRetarget, AnchorUiStack, AnchorUiStackOrder,
+        AnchorUiVisibilityOverride, AnchorUiVisibilityPolicy, AnchorVisibilityContext,
+        AnchorWorldSize, AnchoredUiNodes, AutoAnchor, DeclutterMode, DepthSortKind,
+        HorizontalAnchor, InAnchorCluster, LeaderLineEdge, LeaderLineElbow, Occlusion,
+        OcclusionMode, OcclusionRay, OutsideCameraTest, SmoothingKind, StackDirection,
+        VerticalAnchor, VisibilityRule, WorldSizeMode,
     };
 }
 
 #[derive(SystemSet, Debug, Hash, PartialEq, Eq, Clone)]
 pub enum AnchorUiSystemSet {
     MoveUiNodes,
+    /// Checks if anchored UI nodes are occluded with the 'AnchorOcclusionProvider', runs inside 'MoveUiNodes'
+    CheckOcclusion,
+    /// Replaces anchored UI nodes that are close on screen with a badge, see 'AnchorClusterGroup'
+    Cluster,
+    /// Hides the anchored UI nodes that do not fit in 'AnchorUiBudget', runs after 'MoveUiNodes' when it exists
+    LimitVisible,
+    /// Resolves overlap between anchored UI nodes, runs after 'MoveUiNodes' when 'AnchorUiDeclutter' exists
+    Declutter,
+    /// Draws the 'AnchorLeaderLine's from the placed UI nodes to their targets
+    LeaderLines,
+    /// Orders the anchored UI nodes by their distance to the camera, when 'AnchorUiDepthSort' exists
+    DepthSort,
     UpdateVisibility,
 }
 
@@ -82,6 +148,100 @@ impl AnchorPoint {
             vertical: VerticalAnchor::Bottom,
         }
     }
+    pub fn topmid() -> Self {
+        Self {
+            horizontal: HorizontalAnchor::Mid,
+            vertical: VerticalAnchor::Top,
+        }
+    }
+
+    /// Computes the on screen rect of a UI node of 'size', when this anchorpoint is placed at 'position'
+    ///
+    /// Both 'position' and the returned rect are in logical viewport coordinates, with y pointing down
+    pub fn rect_at(&self, position: Vec2, size: Vec2) -> Rect {
+        let x = match self.horizontal {
+            HorizontalAnchor::Left => position.x,
+            HorizontalAnchor::Mid => position.x - size.x / 2.0,
+            HorizontalAnchor::Right => position.x - size.x,
+        };
+        let y = match self.vertical {
+            VerticalAnchor::Top => position.y,
+            VerticalAnchor::Mid => position.y - size.y / 2.0,
+            VerticalAnchor::Bottom => position.y - size.y,
+        };
+        Rect::from_corners(Vec2::new(x, y), Vec2::new(x, y) + size)
+    }
+}
+
+/// An alternative anchorpoint and offset, used when the UI node does not fit inside the viewport
+#[derive(Default, Reflect, Debug, Clone, Copy, PartialEq)]
+pub struct AnchorFallback {
+    /// Defines where on the UI node the anchorpoint is located when this fallback is used
+    pub anchorpoint: AnchorPoint,
+    /// Offset used instead of 'AnchorUiConfig::offset' when this fallback is used
+    pub offset: Option<Vec3>,
+}
+
+impl AnchorFallback {
+    pub fn new(anchorpoint: AnchorPoint) -> Self {
+        Self {
+            anchorpoint,
+            offset: None,
+        }
+    }
+    pub fn with_offset(mut self, offset: Vec3) -> Self {
+        self.offset = Some(offset);
+        self
+    }
+}
+
+/// Chooses the anchorpoint from which quadrant of the viewport the target is projected into,
+/// so that the UI node always extends away from the centre of the screen
+///
+/// A target in the top left of the screen gets 'AnchorPoint::bottomright', a target in the bottom right gets 'AnchorPoint::topleft' and so on
+#[derive(Default, Reflect, Debug, Clone, Copy, PartialEq)]
+pub struct AutoAnchor {
+    /// Fraction (0.0 - 1.0) of the viewport width and height around the centre, where the mid anchor is used for that axis instead
+    pub dead_zone: Vec2,
+}
+
+impl AutoAnchor {
+    pub fn new(dead_zone: Vec2) -> Self {
+        Self { dead_zone }
+    }
+
+    /// The anchorpoint to use for a target projected to 'position' inside 'viewport'
+    pub fn anchorpoint(&self, position: Vec2, viewport: Rect) -> AnchorPoint {
+        let half_size = viewport.half_size().max(Vec2::splat(f32::EPSILON));
+        // -1.0 to 1.0 from the left/top to the right/bottom edge of the viewport
+        let relative = (position - viewport.center()) / half_size;
+        let dead_zone = self.dead_zone.clamp(Vec2::ZERO, Vec2::ONE);
+
+        let horizontal = if relative.x.abs() <= dead_zone.x {
+            HorizontalAnchor::Mid
+        } else if relative.x < 0.0 {
+            HorizontalAnchor::Right
+        } else {
+            HorizontalAnchor::Left
+        };
+        let vertical = if relative.y.abs() <= dead_zone.y {
+            VerticalAnchor::Mid
+        } else if relative.y < 0.0 {
+            VerticalAnchor::Bottom
+        } else {
+            VerticalAnchor::Top
+        };
+        AnchorPoint {
+            horizontal,
+            vertical,
+        }
+    }
+}
+
+impl From<AnchorPoint> for AnchorFallback {
+    fn from(anchorpoint: AnchorPoint) -> Self {
+        Self::new(anchorpoint)
+    }
 }
 
 /// relationship that defines which uinodes are anchored to this entity
@@ -93,7 +253,18 @@ pub struct AnchoredUiNodes(Vec<Entity>);
 /// position, or chosen as another entities ['GlobalTransformation']
 #[derive(Component, Reflect, Clone, Debug, PartialEq)]
 #[relationship(relationship_target = AnchoredUiNodes)]
-#[require(AnchorUiConfig, Node, AnchorUiVisibility)]
+#[require(
+    AnchorUiConfig,
+    Node,
+    AnchorUiVisibility,
+    ActiveAnchorPoint,
+    AnchorUiProjection,
+    AnchorUiMotion,
+    AnchorUiFixedHistory,
+    AnchorUiOpacity,
+    AnchorUiAnimation,
+    AnchorUiOcclusion
+)]
 pub struct AnchorUiNode {
     /// The Ui will be placed onto the screen, matching where this entity is located in the world
     #[relationship]
@@ -113,12 +284,82 @@ pub struct AnchorUiConfig {
     ///
     /// Ie, if the entity that this Ui is anchored to has a visibility of false, this UI node will get its 'node.display' set to 'Display::None'
     /// if this is set, 'AnchorUiPlugin' will change the 'Visibilty' Component for this between 'Visibility::Visible' and 'Visibility::Hidden'
+    ///
+    /// Shorthand for 'VisibilityRule::TargetVisible', see 'AnchorUiVisibilityPolicy' for more rules
     pub follow_visibility: bool,
 
     /// This will explicitly change the visibility of the UI node to False if the followed entity is outside the camera
     ///
     /// if this is set, 'AnhchorUiPlugin' will will modify the 'Visibilty' Component for this between 'Visibility::Visible' and 'Visibility::Hidden'
+    ///
+    /// Shorthand for 'VisibilityRule::InsideCamera', see 'AnchorUiVisibilityPolicy' for more rules
     pub hide_outside_camera: bool,
+
+    /// How the plugin decides if the target is outside the camera, for 'hide_outside_camera'
+    pub outside_camera_test: OutsideCameraTest,
+
+    /// Hides the UI node while the 'RenderLayers' of its target and the camera do not overlap, so the camera does not render the target
+    ///
+    /// Shorthand for 'VisibilityRule::CameraLayers', see 'AnchorUiVisibilityPolicy' for more rules
+    pub follow_render_layers: bool,
+
+    /// Anchorpoints and offsets that are tried in order if the UI node does not fit inside the viewport
+    /// using 'anchorpoint' and 'offset', the first one that fits is used
+    ///
+    /// If none of them fit, the currently used one is kept
+    pub fallbacks: Vec<AnchorFallback>,
+
+    /// Margin in logical pixels that an earlier anchorpoint must fit inside the viewport with, before the plugin switches back to it
+    ///
+    /// Stops the UI node from flickering between two anchorpoints when it is right at the edge of the viewport
+    pub fallback_hysteresis: f32,
+
+    /// If set, 'anchorpoint' is ignored and instead chosen from where on the screen the target is, see 'AutoAnchor'
+    pub auto_anchor: Option<AutoAnchor>,
+
+    /// If set, the UI node eases towards where it should be on screen instead of snapping there every frame,
+    /// which hides jitter from the target
+    pub smoothing: Option<AnchorSmoothing>,
+
+    /// Rounds the position of the UI node to whole physical pixels, so that it is not rendered in between pixels
+    pub pixel_snapping: bool,
+
+    /// The UI node is not moved unless its position changes by more than this many logical pixels
+    ///
+    /// Stops stationary UI nodes from vibrating, and from triggering change detection and relayout every frame
+    pub dead_zone: f32,
+
+    /// For targets that are moved in 'FixedUpdate', places the UI node in between the targets two last fixed step positions,
+    /// using how far into the next fixed step the frame is, so the UI node moves smoothly even though the target moves in steps
+    pub interpolate_fixed_update: bool,
+
+    /// If set, the UI node is placed where its target is predicted to be from how it has moved on screen the last few frames,
+    /// which removes the lag of one frame that fast moving targets otherwise get
+    pub prediction: Option<AnchorPrediction>,
+
+    /// If set, the UI node is hidden while its anchored world location is nearer or further from the camera than allowed,
+    /// see 'AnchorDistanceCulling'
+    pub distance_culling: Option<AnchorDistanceCulling>,
+
+    /// If set, the UI node is scaled by how far its anchored world location is from the camera, see 'AnchorDistanceScaling'
+    pub distance_scaling: Option<AnchorDistanceScaling>,
+
+    /// If set, the UI node is sized as if it had a fixed size in world units at its target, see 'AnchorWorldSize'
+    ///
+    /// Takes over from 'distance_scaling'
+    pub world_size: Option<AnchorWorldSize>,
+
+    /// If set, the UI node is hidden or dimmed while an 'AnchorOccluder' is in between it and the camera, see 'AnchorOcclusion'
+    pub occlusion: Option<AnchorOcclusion>,
+
+    /// If set, the UI node fades out with its distance from the camera, see 'AnchorDepthFog'
+    pub fog: Option<AnchorDepthFog>,
+
+    /// If set, the UI node fades in and out when the plugin shows or hides it, see 'AnchorFade'
+    pub fade: Option<AnchorFade>,
+
+    /// If set, the UI node animates in and out when the plugin shows or hides it, see 'AnchorTransition'
+    pub transition: Option<AnchorTransition>,
 }
 
 impl AnchorUiConfig {
@@ -134,227 +375,1758 @@ impl AnchorUiConfig {
         self.anchorpoint.vertical = vertical;
         self
     }
-}
+    pub fn with_outside_camera_test(mut self, outside_camera_test: OutsideCameraTest) -> Self {
+        self.outside_camera_test = outside_camera_test;
+        self
+    }
+    pub fn with_render_layers(mut self) -> Self {
+        self.follow_render_layers = true;
+        self
+    }
+    pub fn with_fallback(mut self, fallback: impl Into<AnchorFallback>) -> Self {
+        self.fallbacks.push(fallback.into());
+        self
+    }
+    pub fn with_fallback_hysteresis(mut self, hysteresis: f32) -> Self {
+        self.fallback_hysteresis = hysteresis;
+        self
+    }
+    pub fn with_auto_anchor(mut self, auto_anchor: AutoAnchor) -> Self {
+        self.auto_anchor = Some(auto_anchor);
+        self
+    }
+    pub fn with_smoothing(mut self, smoothing: AnchorSmoothing) -> Self {
+        self.smoothing = Some(smoothing);
+        self
+    }
+    pub fn with_pixel_snapping(mut self) -> Self {
+        self.pixel_snapping = true;
+        self
+    }
+    pub fn with_dead_zone(mut self, dead_zone: f32) -> Self {
+        self.dead_zone = dead_zone;
+        self
+    }
+    pub fn with_fixed_update_interpolation(mut self) -> Self {
+        self.interpolate_fixed_update = true;
+        self
+    }
+    pub fn with_prediction(mut self, prediction: AnchorPrediction) -> Self {
+        self.prediction = Some(prediction);
+        self
+    }
+    pub fn with_distance_culling(mut self, distance_culling: AnchorDistanceCulling) -> Self {
+        self.distance_culling = Some(distance_culling);
+        self
+    }
+    pub fn with_distance_scaling(mut self, distance_scaling: AnchorDistanceScaling) -> Self {
+        self.distance_scaling = Some(distance_scaling);
+        self
+    }
+    pub fn with_world_size(mut self, world_size: AnchorWorldSize) -> Self {
+        self.world_size = Some(world_size);
+        self
+    }
+    pub fn with_occlusion(mut self, occlusion: AnchorOcclusion) -> Self {
+        self.occlusion = Some(occlusion);
+        self
+    }
+    pub fn with_fog(mut self, fog: AnchorDepthFog) -> Self {
+        self.fog = Some(fog);
+        self
+    }
+    pub fn with_fade(mut self, fade: AnchorFade) -> Self {
+        self.fade = Some(fade);
+        self
+    }
+    pub fn with_transition(mut self, transition: AnchorTransition) -> Self {
+        self.transition = Some(transition);
+        self
+    }
 
-impl AnchorUiNode {
-    /// Will anchor the midpoint of this UI element towards the chosen entity
-    pub fn to_entity(entity: Entity) -> Self {
-        Self { target: entity }
+    /// The anchorpoint and offset used for the given fallback, where 'None' is the primary 'anchorpoint' and 'offset'
+    pub fn candidate(&self, fallback: Option<usize>) -> Option<AnchorFallback> {
+        match fallback {
+            None => Some(AnchorFallback {
+                anchorpoint: self.anchorpoint,
+                offset: self.offset,
+            }),
+            Some(index) => self.fallbacks.get(index).copied(),
+        }
     }
 }
 
-pub struct AnchorUiPlugin<SingleCameraMarker: Component> {
-    _component: PhantomData<SingleCameraMarker>,
+/// How the plugin decides if the target of an anchored UI node is outside the camera
+#[derive(Default, Reflect, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutsideCameraTest {
+    /// The anchored world location has to be in front of the camera
+    #[default]
+    Point,
+    /// Uses the frustum culling bevy does for the camera, so a large mesh stays inside the camera while any part of it is in view
+    ///
+    /// Only the 'VisibleEntities' of the camera with the marker component count, other cameras and shadow casting lights seeing
+    /// the target do not. Targets without an 'Aabb', such as entities without a mesh, use 'Point'
+    ViewVisibility,
 }
 
-impl<SingleCameraMarker: Component> AnchorUiPlugin<SingleCameraMarker> {
-    pub fn new() -> Self {
+/// How 'AnchorSmoothing' eases the UI node towards its target position
+#[derive(Default, Reflect, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmoothingKind {
+    /// Covers about 63% of the remaining distance every 'time_constant' seconds
+    #[default]
+    Exponential,
+    /// A critically damped spring, which also eases changes in speed, and reaches the target in about 'time_constant' seconds
+    Spring,
+}
+
+/// Configures how an anchored UI node eases towards where it should be on screen
+///
+/// The UI node still snaps into place when it is first placed, when it comes back into view,
+/// and when its target moves further than 'snap_distance' in one frame
+#[derive(Reflect, Debug, Clone, Copy, PartialEq)]
+pub struct AnchorSmoothing {
+    pub kind: SmoothingKind,
+    /// How quickly the UI node catches up with its target, in seconds
+    pub time_constant: f32,
+    /// Distance in logical pixels from the current position, at which the UI node snaps to its target instead of easing there
+    pub snap_distance: f32,
+}
+
+impl Default for AnchorSmoothing {
+    fn default() -> Self {
         Self {
-            _component: PhantomData,
+            kind: SmoothingKind::Exponential,
+            time_constant: 0.1,
+            snap_distance: 300.0,
         }
     }
 }
 
-impl<SingleCameraMarker: Component> Default for AnchorUiPlugin<SingleCameraMarker> {
-    fn default() -> Self {
-        Self::new()
+impl AnchorSmoothing {
+    pub fn exponential(time_constant: f32) -> Self {
+        Self {
+            kind: SmoothingKind::Exponential,
+            time_constant,
+            ..Default::default()
+        }
+    }
+    pub fn spring(time_constant: f32) -> Self {
+        Self {
+            kind: SmoothingKind::Spring,
+            time_constant,
+            ..Default::default()
+        }
+    }
+    pub fn with_snap_distance(mut self, snap_distance: f32) -> Self {
+        self.snap_distance = snap_distance;
+        self
     }
 }
 
-impl<SingleCameraMarker: Component> Plugin for AnchorUiPlugin<SingleCameraMarker> {
-    fn build(&self, app: &mut App) {
-        app.configure_sets(
-            PostUpdate,
-            AnchorUiSystemSet::MoveUiNodes
-                .before(TransformSystems::Propagate)
-                .before(UiSystems::Layout),
-        );
-        app.configure_sets(
-            PostUpdate,
-            AnchorUiSystemSet::UpdateVisibility.after(VisibilitySystems::VisibilityPropagate),
-        );
+/// Configures how far ahead 'AnchorUiConfig::prediction' extrapolates the on screen position of the target
+#[derive(Reflect, Debug, Clone, Copy, PartialEq)]
+pub struct AnchorPrediction {
+    /// How far ahead to predict, in number of frames of the current frame time
+    pub frames: f32,
+    /// How many of the last frames are used to estimate how fast the target moves on screen
+    pub samples: usize,
+    /// The furthest in logical pixels that the prediction moves the UI node away from the projected position
+    pub max_offset: f32,
+}
 
-        app.add_systems(
-            PostUpdate,
-            system_move_ui_nodes::<SingleCameraMarker>.in_set(AnchorUiSystemSet::MoveUiNodes),
-        );
-        app.add_systems(
-            PostUpdate,
-            (system_follow_visibility, system_update_visibility_of_uinode)
-                .chain()
-                .in_set(AnchorUiSystemSet::UpdateVisibility),
-        );
+impl Default for AnchorPrediction {
+    fn default() -> Self {
+        Self {
+            frames: 1.0,
+            samples: 4,
+            max_offset: 50.0,
+        }
+    }
+}
 
-        app.register_type::<AnchorUiNode>();
+impl AnchorPrediction {
+    pub fn with_frames(mut self, frames: f32) -> Self {
+        self.frames = frames;
+        self
+    }
+    pub fn with_samples(mut self, samples: usize) -> Self {
+        self.samples = samples;
+        self
+    }
+    pub fn with_max_offset(mut self, max_offset: f32) -> Self {
+        self.max_offset = max_offset;
+        self
     }
 }
 
-#[derive(Component, PartialEq, Eq, Default)]
-#[require(Visibility)]
-struct AnchorUiVisibility {
-    pub outside_camera: bool,
-    pub anchor_visible: bool,
+/// Hides anchored UI nodes outside of a range of distances from the camera, measured along the view direction
+#[derive(Reflect, Debug, Clone, Copy, PartialEq)]
+pub struct AnchorDistanceCulling {
+    /// The UI node is hidden while it is nearer to the camera than this
+    pub min: f32,
+    /// The UI node is hidden while it is further from the camera than this
+    pub max: f32,
+    /// How far back inside the range a hidden UI node has to come before it is shown again,
+    /// stops it from flickering when it is right at 'min' or 'max'
+    pub hysteresis: f32,
 }
 
-fn system_follow_visibility(
-    followed: Query<&InheritedVisibility>,
-    uinodes: Query<(&mut AnchorUiVisibility, &AnchorUiNode)>,
-) {
-    for (mut visibility, anchor) in uinodes {
-        if let Ok(followed_visibility) = followed.get(anchor.target) {
-            visibility.anchor_visible = followed_visibility.get();
+impl Default for AnchorDistanceCulling {
+    fn default() -> Self {
+        Self {
+            min: 0.0,
+            max: f32::INFINITY,
+            hysteresis: 0.0,
         }
     }
 }
 
-fn system_update_visibility_of_uinode(
-    uinodes: Query<(&mut Visibility, &AnchorUiVisibility, &AnchorUiConfig)>,
-) {
-    for (mut visibility, anchor_vis, config) in uinodes {
-        match (config.hide_outside_camera, config.follow_visibility) {
-            (true, true) => {
-                if anchor_vis.outside_camera {
-                    *visibility = Visibility::Hidden;
-                } else {
-                    if anchor_vis.anchor_visible {
-                        visibility.set_if_neq(Visibility::Visible);
-                    } else {
-                        visibility.set_if_neq(Visibility::Hidden);
-                    }
-                }
-            }
-            (false, true) => {
-                if anchor_vis.anchor_visible {
-                    visibility.set_if_neq(Visibility::Visible);
-                } else {
-                    visibility.set_if_neq(Visibility::Hidden);
-                }
-            }
-            (true, false) => {
-                if anchor_vis.outside_camera {
-                    visibility.set_if_neq(Visibility::Hidden);
-                } else {
-                    visibility.set_if_neq(Visibility::Visible);
-                }
-            }
-            (false, false) => {}
+impl AnchorDistanceCulling {
+    pub fn new(min: f32, max: f32) -> Self {
+        Self {
+            min,
+            max,
+            ..Default::default()
         }
     }
+    /// Only hides UI nodes that are further away than 'max'
+    pub fn max(max: f32) -> Self {
+        Self::new(0.0, max)
+    }
+    pub fn with_hysteresis(mut self, hysteresis: f32) -> Self {
+        self.hysteresis = hysteresis;
+        self
+    }
+
+    /// If a UI node at 'depth' is out of range, 'was_out_of_range' being if it was last frame
+    pub fn is_out_of_range(&self, depth: f32, was_out_of_range: bool) -> bool {
+        let margin = if was_out_of_range {
+            self.hysteresis.max(0.0)
+        } else {
+            0.0
+        };
+        depth < self.min + margin || depth > self.max - margin
+    }
 }
 
-fn system_move_ui_nodes<C: Component>(
-    cameras: Query<(Entity, &Camera), With<C>>,
-    window: Query<&Window, With<PrimaryWindow>>,
-    mut uinodes: Query<(
-        Entity,
-        &mut Node,
-        &mut AnchorUiVisibility,
-        &ComputedNode,
-        &AnchorUiNode,
-        &AnchorUiConfig,
-    )>,
-    transformhelper: TransformHelper,
-) {
-    let window = match window.single() {
-        Ok(window) => window,
-        Err(QuerySingleError::NoEntities(_)) => return,
-        Err(err @ QuerySingleError::MultipleEntities(_)) => {
-            bevy::log::error!("more than one primary window: {err}");
-            return;
-        }
-    };
-    let (camera_entity, main_camera) = match cameras.single() {
-        Ok(camera) => camera,
-        Err(QuerySingleError::NoEntities(_)) => return,
-        Err(err @ QuerySingleError::MultipleEntities(_)) => {
-            bevy::log::error!("more than one camera with the specified marker component: {err}");
-            return;
-        }
-    };
-    let Ok(main_camera_transform) = transformhelper.compute_global_transform(camera_entity) else {
-        warn!("Failed computing global transform for Camera Entity");
-        return;
-    };
+/// Scales anchored UI nodes down as they move away from the camera, applied through their 'UiTransform'
+///
+/// The anchorpoint stays where it should be for the scaled size of the UI node
+#[derive(Reflect, Debug, Clone, Copy, PartialEq)]
+pub struct AnchorDistanceScaling {
+    /// The distance from a perspective camera at which the UI node has its normal size
+    pub reference_distance: f32,
+    /// The 'OrthographicProjection::scale' of an orthographic camera at which the UI node has its normal size
+    pub reference_zoom: f32,
+    /// The smallest scale the UI node is shrunk to
+    pub min_scale: f32,
+    /// The largest scale the UI node is grown to
+    pub max_scale: f32,
+}
 
-    for (uientity, mut node, mut vis, computed_node, uinode, uianchorconf) in uinodes.iter_mut() {
-        if node.display == Display::None {
-            // The node is not displayed, skip it
-            continue;
+impl Default for AnchorDistanceScaling {
+    fn default() -> Self {
+        Self {
+            reference_distance: 10.0,
+            reference_zoom: 1.0,
+            min_scale: 0.25,
+            max_scale: 1.0,
         }
+    }
+}
 
-        // what location should we sync to
-        let world_location = if let Ok(gt) = transformhelper.compute_global_transform(uinode.target)
-        {
-            gt.translation()
-        } else {
-            warn!("AnchorTarget({}) failed to compute global transform, uinode: {uientity} will not be updated", uinode.target);
-            continue;
-        };
+impl AnchorDistanceScaling {
+    /// The UI node has its normal size at 'reference_distance' from the camera, and gets smaller further away
+    pub fn new(reference_distance: f32) -> Self {
+        Self {
+            reference_distance,
+            ..Default::default()
+        }
+    }
+    pub fn with_reference_zoom(mut self, reference_zoom: f32) -> Self {
+        self.reference_zoom = reference_zoom;
+        self
+    }
+    pub fn with_scale_range(mut self, min_scale: f32, max_scale: f32) -> Self {
+        self.min_scale = min_scale;
+        self.max_scale = max_scale;
+        self
+    }
 
-        let world_location = if let Some(offset) = uianchorconf.offset {
-            world_location + offset
-        } else {
-            world_location
+    /// The scale of a UI node at 'depth' from a camera with 'projection'
+    pub fn scale(&self, projection: Option<&Projection>, depth: f32) -> f32 {
+        let scale = match projection {
+            Some(Projection::Orthographic(orthographic)) => {
+                self.reference_zoom / orthographic.scale.max(f32::EPSILON)
+            }
+            _ => self.reference_distance / depth.max(f32::EPSILON),
         };
+        scale.max(self.min_scale).min(self.max_scale)
+    }
+}
 
-        let Ok(position) =
-            main_camera.world_to_viewport_with_depth(&main_camera_transform, world_location)
-        else {
-            // Object is offscreen
-            bevy::log::debug!("world location is offscreen, and thus we dont change the position");
-            vis.outside_camera = true;
-            continue;
-        };
+/// How 'AnchorWorldSize' makes the UI node its size on screen
+#[derive(Default, Reflect, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorldSizeMode {
+    /// Scales the UI node through its 'UiTransform', keeping its layout as is
+    #[default]
+    Scale,
+    /// Sets 'Node::width' and 'Node::height' in pixels, so the contents are laid out again at the new size
+    Resize,
+}
 
-        vis.outside_camera = false;
+/// Makes an anchored UI node behave as if it had a fixed size in world units, such as signage that is 2 units wide
+///
+/// Uses how many logical pixels one world unit covers at the target, from the projection of the camera
+#[derive(Reflect, Debug, Clone, Copy, PartialEq)]
+pub struct AnchorWorldSize {
+    /// Width of the UI node in world units
+    pub width: f32,
+    /// Height of the UI node in world units, if 'None' the height follows from the width and the layout
+    ///
+    /// In 'WorldSizeMode::Scale' a height stretches the UI node, scaling it separately on each axis
+    pub height: Option<f32>,
+    pub mode: WorldSizeMode,
+    /// The smallest scale the UI node is shrunk to in 'WorldSizeMode::Scale', so it stays readable far away
+    pub min_scale: f32,
+    /// The largest scale the UI node is grown to in 'WorldSizeMode::Scale'
+    pub max_scale: f32,
+}
 
-        if node.as_ref().position_type != PositionType::Absolute {
-            node.position_type = PositionType::Absolute;
+impl AnchorWorldSize {
+    /// The UI node is 'width' world units wide
+    pub fn new(width: f32) -> Self {
+        Self {
+            width,
+            height: None,
+            mode: WorldSizeMode::Scale,
+            min_scale: 0.0,
+            max_scale: f32::INFINITY,
+        }
+    }
+    pub fn with_height(mut self, height: f32) -> Self {
+        self.height = Some(height);
+        self
+    }
+    pub fn with_mode(mut self, mode: WorldSizeMode) -> Self {
+        self.mode = mode;
+        self
+    }
+    pub fn with_scale_range(mut self, min_scale: f32, max_scale: f32) -> Self {
+        self.min_scale = min_scale;
+        self.max_scale = max_scale;
+        self
+    }
+
+    /// The 'UiTransform' scale for a UI node laid out at 'layout_size', where one world unit is 'pixels_per_unit' logical pixels
+    pub fn scale(&self, pixels_per_unit: f32, layout_size: Vec2) -> Vec2 {
+        if self.mode != WorldSizeMode::Scale || layout_size.x <= 0.0 {
+            return Vec2::ONE;
+        }
+        let x = self.width * pixels_per_unit / layout_size.x;
+        let y = match self.height {
+            Some(height) if layout_size.y > 0.0 => height * pixels_per_unit / layout_size.y,
+            _ => x,
+        };
+        Vec2::new(x, y)
+            .max(Vec2::splat(self.min_scale))
+            .min(Vec2::splat(self.max_scale))
+    }
+}
+
+/// What happens to an anchored UI node whose target is projected outside of its 'AnchorUiContainer'
+#[derive(Default, Reflect, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnchorUiContainerMode {
+    /// The UI node is hidden while its anchorpoint is outside of the container
+    #[default]
+    Hide,
+    /// The UI node is moved so that it stays inside the container
+    Clamp,
+}
+
+/// Constrains an anchored UI node to the on screen rect of another UI node, such as a map panel
+///
+/// The container rect is also used instead of the viewport when choosing between 'AnchorUiConfig::fallbacks'
+/// and when using 'AnchorUiConfig::auto_anchor'
+#[derive(Component, Reflect, Clone, Debug, PartialEq)]
+pub struct AnchorUiContainer {
+    /// The UI node whose computed rect the anchored UI node must stay within
+    pub container: Entity,
+    /// What to do when the anchored UI node ends up outside of the container
+    pub mode: AnchorUiContainerMode,
+}
+
+impl AnchorUiContainer {
+    /// Hides the anchored UI node while it is outside of 'container'
+    pub fn hide(container: Entity) -> Self {
+        Self {
+            container,
+            mode: AnchorUiContainerMode::Hide,
+        }
+    }
+    /// Keeps the anchored UI node inside of 'container'
+    pub fn clamp(container: Entity) -> Self {
+        Self {
+            container,
+            mode: AnchorUiContainerMode::Clamp,
+        }
+    }
+}
+
+/// Which of the anchorpoints in 'AnchorUiConfig' the plugin currently uses to place the UI node
+#[derive(Component, Reflect, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ActiveAnchorPoint {
+    /// 'None' when the primary 'AnchorUiConfig::anchorpoint' is used, otherwise the index into 'AnchorUiConfig::fallbacks'
+    pub fallback: Option<usize>,
+}
+
+/// Where the plugin last placed an anchored UI node on screen, updated in 'AnchorUiSystemSet::MoveUiNodes'
+///
+/// All positions are in logical viewport coordinates, with y pointing down
+#[derive(Component, Reflect, Debug, Clone, Copy, PartialEq)]
+pub struct AnchorUiProjection {
+    /// Where the anchored world location is projected to on screen
+    pub position: Vec2,
+    /// Where the target itself is projected to on screen, without 'AnchorUiConfig::offset'
+    pub target_position: Vec2,
+    /// The anchored world location, including the offset
+    pub world_position: Vec3,
+    /// Distance from the camera to the anchored world location, along the view direction
+    pub depth: f32,
+    /// The rect the UI node is placed at, including 'scale'
+    pub rect: Rect,
+    /// The scale the UI node is drawn with, from 'AnchorUiConfig::distance_scaling' or 'AnchorUiConfig::world_size'
+    pub scale: Vec2,
+    /// How many logical pixels one world unit covers at the target, 0.0 if it could not be computed
+    pub pixels_per_unit: f32,
+}
+
+impl Default for AnchorUiProjection {
+    fn default() -> Self {
+        Self {
+            position: Vec2::ZERO,
+            target_position: Vec2::ZERO,
+            world_position: Vec3::ZERO,
+            depth: 0.0,
+            rect: Rect::default(),
+            scale: Vec2::ONE,
+            pixels_per_unit: 0.0,
+        }
+    }
+}
+
+/// Resource that limits how many anchored UI nodes can be visible at the same time
+///
+/// Candidates are ranked by their 'AnchorPriority', then by how close they are to the camera, and the rest are hidden
+#[derive(Resource, Reflect, Debug, Clone, Copy, PartialEq, Eq)]
+#[reflect(Resource)]
+pub struct AnchorUiBudget {
+    /// The maximum number of anchored UI nodes that are visible at once
+    pub max_visible: usize,
+}
+
+impl AnchorUiBudget {
+    pub fn new(max_visible: usize) -> Self {
+        Self { max_visible }
+    }
+}
+
+/// Priority of an anchored UI node when the plugin has to choose between nodes, higher values win
+///
+/// Nodes without this component have priority 0
+#[derive(Component, Reflect, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct AnchorPriority(pub i32);
+
+impl AnchorUiNode {
+    /// Will anchor the midpoint of this UI element towards the chosen entity
+    pub fn to_entity(entity: Entity) -> Self {
+        Self { target: entity }
+    }
+}
+
+/// Present on an anchored UI node while it animates from its previous target to its current one
+///
+/// Usually inserted with 'AnchorUiCommandsExt::retarget_anchor', and removed by the plugin once the animation is done
+#[derive(Component, Reflect, Debug, Clone, PartialEq)]
+pub struct AnchorUiRetarget {
+    /// The target the UI node animates away from
+    pub from: Entity,
+    /// How long the animation takes, in seconds
+    pub duration: f32,
+    /// How far into the animation the UI node is, in seconds
+    pub elapsed: f32,
+    pub easing: EaseFunction,
+}
+
+impl AnchorUiRetarget {
+    /// How far from the previous target towards the current one the UI node is, from 0.0 to 1.0
+    pub fn progress(&self) -> f32 {
+        if self.duration <= 0.0 {
+            return 1.0;
+        }
+        self.easing
+            .sample_clamped((self.elapsed / self.duration).clamp(0.0, 1.0))
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.elapsed >= self.duration
+    }
+}
+
+/// Extension for 'EntityCommands' on anchored UI nodes
+pub trait AnchorUiCommandsExt {
+    /// Anchors the UI node to 'target', moving it there from its current target over 'duration' seconds
+    ///
+    /// Both targets are tracked during the animation, so it stays correct if either of them moves
+    fn retarget_anchor(&mut self, target: Entity, duration: f32, easing: EaseFunction)
+        -> &mut Self;
+}
+
+impl AnchorUiCommandsExt for EntityCommands<'_> {
+    fn retarget_anchor(
+        &mut self,
+        target: Entity,
+        duration: f32,
+        easing: EaseFunction,
+    ) -> &mut Self {
+        self.queue(move |mut entity: EntityWorldMut| {
+            let from = entity.get::<AnchorUiNode>().map(|anchor| anchor.target);
+            entity.insert(AnchorUiNode::to_entity(target));
+            match from {
+                Some(from) if from != target && duration > 0.0 => {
+                    entity.insert(AnchorUiRetarget {
+                        from,
+                        duration,
+                        elapsed: 0.0,
+                        easing,
+                    });
+                }
+                _ => {
+                    entity.remove::<AnchorUiRetarget>();
+                }
+            }
+        })
+    }
+}
+
+pub struct AnchorUiPlugin<SingleCameraMarker: Component> {
+    _component: PhantomData<SingleCameraMarker>,
+    declutter: Option<AnchorUiDeclutter>,
+    budget: Option<AnchorUiBudget>,
+    depth_sort: Option<AnchorUiDepthSort>,
+    occlusion_system: fn(&mut App),
+}
+
+impl<SingleCameraMarker: Component> AnchorUiPlugin<SingleCameraMarker> {
+    pub fn new() -> Self {
+        Self {
+            _component: PhantomData,
+            declutter: None,
+            budget: None,
+            depth_sort: None,
+            occlusion_system: add_occlusion_system::<SingleCameraMarker, AabbOcclusion>,
+        }
+    }
+    /// Enables resolving overlap between anchored UI nodes, see 'AnchorUiDeclutter'
+    pub fn with_declutter(mut self, declutter: AnchorUiDeclutter) -> Self {
+        self.declutter = Some(declutter);
+        self
+    }
+    /// Limits how many anchored UI nodes are visible at once, see 'AnchorUiBudget'
+    pub fn with_max_visible(mut self, max_visible: usize) -> Self {
+        self.budget = Some(AnchorUiBudget::new(max_visible));
+        self
+    }
+    /// Uses 'P' instead of 'AabbOcclusion' to check if anchored UI nodes are occluded, see 'AnchorOcclusionProvider'
+    pub fn with_occlusion_provider<P: SystemParam + 'static>(mut self) -> Self
+    where
+        for<'w, 's> P::Item<'w, 's>: AnchorOcclusionProvider,
+    {
+        self.occlusion_system = add_occlusion_system::<SingleCameraMarker, P>;
+        self
+    }
+    /// Draws anchored UI nodes nearer to the camera on top, see 'AnchorUiDepthSort'
+    pub fn with_depth_sort(mut self, depth_sort: AnchorUiDepthSort) -> Self {
+        self.depth_sort = Some(depth_sort);
+        self
+    }
+}
+
+impl<SingleCameraMarker: Component> Default for AnchorUiPlugin<SingleCameraMarker> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<SingleCameraMarker: Component> Plugin for AnchorUiPlugin<SingleCameraMarker> {
+    fn build(&self, app: &mut App) {
+        app.configure_sets(
+            PostUpdate,
+            AnchorUiSystemSet::MoveUiNodes
+                .before(TransformSystems::Propagate)
+                .before(UiSystems::Layout),
+        );
+        app.configure_sets(
+            PostUpdate,
+            AnchorUiSystemSet::CheckOcclusion.in_set(AnchorUiSystemSet::MoveUiNodes),
+        );
+        app.configure_sets(
+            PostUpdate,
+            AnchorUiSystemSet::Cluster
+                .after(AnchorUiSystemSet::MoveUiNodes)
+                .before(AnchorUiSystemSet::LimitVisible)
+                .before(TransformSystems::Propagate)
+                .before(UiSystems::Layout),
+        );
+        app.configure_sets(
+            PostUpdate,
+            AnchorUiSystemSet::LimitVisible
+                .after(AnchorUiSystemSet::MoveUiNodes)
+                .before(AnchorUiSystemSet::Declutter),
+        );
+        app.configure_sets(
+            PostUpdate,
+            AnchorUiSystemSet::Declutter
+                .after(AnchorUiSystemSet::MoveUiNodes)
+                .before(TransformSystems::Propagate)
+                .before(UiSystems::Layout),
+        );
+        app.configure_sets(
+            PostUpdate,
+            AnchorUiSystemSet::LeaderLines
+                .after(AnchorUiSystemSet::Declutter)
+                .before(TransformSystems::Propagate)
+                .before(UiSystems::Layout),
+        );
+        app.configure_sets(
+            PostUpdate,
+            AnchorUiSystemSet::DepthSort
+                .after(AnchorUiSystemSet::LeaderLines)
+                .before(UiSystems::Stack),
+        );
+        app.configure_sets(
+            PostUpdate,
+            AnchorUiSystemSet::UpdateVisibility
+                .after(AnchorUiSystemSet::LeaderLines)
+                .after(VisibilitySystems::VisibilityPropagate)
+                .after(VisibilitySystems::CheckVisibility),
+        );
+
+        app.add_systems(FixedPostUpdate, system_record_fixed_update_positions);
+        app.add_systems(
+            PostUpdate,
+            (
+                system_move_ui_nodes::<SingleCameraMarker>,
+                system_finish_retargets,
+                stack::system_stack_ui_nodes.run_if(any_with_component::<AnchorUiStack>),
+            )
+                .chain()
+                .before(AnchorUiSystemSet::CheckOcclusion)
+                .in_set(AnchorUiSystemSet::MoveUiNodes),
+        );
+        app.add_systems(
+            PostUpdate,
+            visibility::system_evaluate_visibility_rules::<false>
+                .after(AnchorUiSystemSet::CheckOcclusion)
+                .in_set(AnchorUiSystemSet::MoveUiNodes),
+        );
+        (self.occlusion_system)(app);
+        app.add_systems(
+            PostUpdate,
+            (
+                cluster::system_release_cluster_members,
+                cluster::system_cluster_ui_nodes
+                    .run_if(any_with_component::<cluster::AnchorClusterGroup>),
+                cluster::system_update_cluster_counts,
+            )
+                .chain()
+                .in_set(AnchorUiSystemSet::Cluster),
+        );
+        app.add_systems(
+            PostUpdate,
+            (
+                system_limit_visible_ui_nodes.run_if(resource_exists::<AnchorUiBudget>),
+                system_reset_visible_limit.run_if(resource_removed::<AnchorUiBudget>),
+            )
+                .in_set(AnchorUiSystemSet::LimitVisible),
+        );
+        app.add_systems(
+            PostUpdate,
+            (
+                declutter::system_declutter_ui_nodes.run_if(resource_exists::<AnchorUiDeclutter>),
+                declutter::system_reset_declutter.run_if(resource_removed::<AnchorUiDeclutter>),
+            )
+                .in_set(AnchorUiSystemSet::Declutter),
+        );
+        app.add_systems(
+            PostUpdate,
+            system_mark_moved_ui_nodes
+                .after(AnchorUiSystemSet::Declutter)
+                .before(AnchorUiSystemSet::LeaderLines),
+        );
+        app.add_systems(
+            PostUpdate,
+            (
+                system_follow_visibility::<SingleCameraMarker>,
+                system_check_render_layers::<SingleCameraMarker>,
+                visibility::system_evaluate_visibility_rules::<true>,
+                system_update_visibility_of_uinode,
+                animation::system_apply_ui_transform,
+                opacity::system_depth_fog::<SingleCameraMarker>,
+                opacity::system_apply_opacity,
+                leader::system_leader_line_visibility,
+            )
+                .chain()
+                .in_set(AnchorUiSystemSet::UpdateVisibility),
+        );
+        app.add_systems(
+            PostUpdate,
+            leader::system_update_leader_lines.in_set(AnchorUiSystemSet::LeaderLines),
+        );
+        app.add_systems(
+            PostUpdate,
+            depth_sort::system_depth_sort_ui_nodes
+                .run_if(resource_exists::<AnchorUiDepthSort>)
+                .in_set(AnchorUiSystemSet::DepthSort),
+        );
+
+        app.register_type::<AnchorUiNode>();
+        app.register_type::<AnchorUiConfig>();
+        app.register_type::<ActiveAnchorPoint>();
+        app.register_type::<AnchorUiContainer>();
+        app.register_type::<AnchorUiProjection>();
+        app.register_type::<AnchorPriority>();
+        app.register_type::<InAnchorCluster>();
+        app.register_type::<AnchorUiCluster>();
+        app.register_type::<AnchorUiRetarget>();
+        app.register_type::<AnchorUiOpacity>();
+        app.register_type::<AnchorUiAnimation>();
+        app.register_type::<AnchorUiVisibilityOverride>();
+        app.register_type::<AnchorOccluder>();
+        app.register_type::<AnchorUiOcclusion>();
+        app.register_type::<AnchorUiStack>();
+        app.register_type::<AnchorUiStackOrder>();
+        app.register_type::<AnchorLeaderLine>();
+        app.register_type::<LeaderLineOf>();
+
+        if let Some(declutter) = &self.declutter {
+            app.insert_resource(declutter.clone());
+        }
+        if let Some(budget) = self.budget {
+            app.insert_resource(budget);
+        }
+        if let Some(depth_sort) = self.depth_sort {
+            app.insert_resource(depth_sort);
         }
+    }
+}
+
+fn add_occlusion_system<C: Component, P: SystemParam + 'static>(app: &mut App)
+where
+    for<'w, 's> P::Item<'w, 's>: AnchorOcclusionProvider,
+{
+    app.add_systems(
+        PostUpdate,
+        occlusion::system_check_occlusion::<C, P>.in_set(AnchorUiSystemSet::CheckOcclusion),
+    );
+}
+
+/// How the UI node has moved on screen, used by 'AnchorSmoothing' and 'AnchorPrediction'
+#[derive(Component, PartialEq, Default)]
+pub(crate) struct AnchorUiMotion {
+    /// The smoothed top left corner of the UI node, 'None' until it has been placed
+    pub position: Option<Vec2>,
+    pub velocity: Vec2,
+    /// The last projected positions of the target, along with the elapsed time they were projected at
+    pub samples: VecDeque<(f32, Vec2)>,
+    /// The left and bottom of the UI node the last time it was marked as moved for layout
+    pub laid_out: Option<Vec2>,
+}
+
+impl AnchorUiMotion {
+    /// Records the projected 'position' of the target, and returns how far from it the target is predicted to be
+    fn predict(
+        &mut self,
+        prediction: &AnchorPrediction,
+        position: Vec2,
+        elapsed: f32,
+        dt: f32,
+    ) -> Vec2 {
+        self.samples.push_back((elapsed, position));
+        while self.samples.len() > prediction.samples.max(2) {
+            self.samples.pop_front();
+        }
+        let (Some((first_time, first)), Some((last_time, last))) =
+            (self.samples.front(), self.samples.back())
+        else {
+            return Vec2::ZERO;
+        };
+        let duration = last_time - first_time;
+        if duration <= 0.0 {
+            return Vec2::ZERO;
+        }
+        let velocity = (*last - *first) / duration;
+        (velocity * dt * prediction.frames).clamp_length_max(prediction.max_offset.max(0.0))
+    }
+
+    /// Eases towards 'target' over 'dt' seconds, and returns the new position
+    fn smooth(&mut self, smoothing: &AnchorSmoothing, target: Vec2, dt: f32) -> Vec2 {
+        let current = match self.position {
+            Some(current) if current.distance(target) <= smoothing.snap_distance => current,
+            // first placement or teleport
+            _ => {
+                self.position = Some(target);
+                self.velocity = Vec2::ZERO;
+                return target;
+            }
+        };
+        let time_constant = smoothing.time_constant.max(f32::EPSILON);
+        let next = match smoothing.kind {
+            SmoothingKind::Exponential => {
+                self.velocity = Vec2::ZERO;
+                target + (current - target) * (-dt / time_constant).exp()
+            }
+            SmoothingKind::Spring => {
+                // closed form approximation of a critically damped spring, stable for any 'dt'
+                let omega = 2.0 / time_constant;
+                let x = omega * dt;
+                let decay = 1.0 / (1.0 + x + 0.48 * x * x + 0.235 * x * x * x);
+                let change = current - target;
+                let temp = (self.velocity + omega * change) * dt;
+                self.velocity = (self.velocity - omega * temp) * decay;
+                target + (change + temp) * decay
+            }
+        };
+        self.position = Some(next);
+        next
+    }
+
+    /// Forget the smoothed position, so that the UI node snaps into place the next time it is placed
+    fn reset_smoothing(&mut self) {
+        if self.position.is_some() {
+            self.position = None;
+            self.velocity = Vec2::ZERO;
+        }
+    }
+
+    /// Forget everything, used when the target is no longer projected
+    fn reset(&mut self) {
+        self.reset_smoothing();
+        if !self.samples.is_empty() {
+            self.samples.clear();
+        }
+    }
+}
+
+/// The world position of the target at the two last fixed steps, used by 'AnchorUiConfig::interpolate_fixed_update'
+#[derive(Component, PartialEq, Default)]
+pub(crate) struct AnchorUiFixedHistory {
+    /// The target the positions were recorded for
+    pub target: Option<Entity>,
+    pub previous: Option<Vec3>,
+    pub current: Option<Vec3>,
+}
+
+impl AnchorUiFixedHistory {
+    /// The interpolated world position of 'target', 'overstep' being the fraction of a fixed step since the last one
+    fn interpolate(&self, target: Entity, overstep: f32) -> Option<Vec3> {
+        if self.target != Some(target) {
+            return None;
+        }
+        Some(self.previous?.lerp(self.current?, overstep))
+    }
+}
+
+fn system_record_fixed_update_positions(
+    uinodes: Query<(&mut AnchorUiFixedHistory, &AnchorUiNode, &AnchorUiConfig)>,
+    transformhelper: TransformHelper,
+) {
+    for (mut history, anchor, config) in uinodes {
+        if !config.interpolate_fixed_update {
+            if history.current.is_some() {
+                *history = AnchorUiFixedHistory::default();
+            }
+            continue;
+        }
+        let Ok(gt) = transformhelper.compute_global_transform(anchor.target) else {
+            continue;
+        };
+        // the positions of a previous target would pull the UI node back towards it
+        if history.target != Some(anchor.target) {
+            *history = AnchorUiFixedHistory {
+                target: Some(anchor.target),
+                ..Default::default()
+            };
+        }
+        let current = gt.translation();
+        history.previous = Some(history.current.unwrap_or(current));
+        history.current = Some(current);
+    }
+}
+
+#[derive(Component, PartialEq, Eq)]
+#[require(Visibility)]
+pub(crate) struct AnchorUiVisibility {
+    pub outside_camera: bool,
+    pub anchor_visible: bool,
+    /// If the target is in the 'VisibleEntities' of the camera, set while it has an 'Aabb' that bevy frustum culls with
+    pub target_in_view: Option<bool>,
+    /// If the 'RenderLayers' of the target overlap with those of the camera, true until it has been checked
+    pub on_camera_layers: bool,
+    /// Set while the node has an 'AnchorUiVisibilityOverride', true when it is forced hidden
+    pub forced: Option<bool>,
+    /// Set while any 'VisibilityRule' applies to the node, true when one of them is not met
+    pub rules_hidden: Option<bool>,
+    /// If a 'VisibilityRule::Custom' hid the node when the rules were last fully evaluated
+    pub custom_hidden: bool,
+    /// Set while the node has 'AnchorUiConfig::occlusion' in 'OcclusionMode::Hide', true when it is occluded
+    pub occluded: Option<bool>,
+    /// Set while the node has 'AnchorUiConfig::distance_culling', true when it is out of range
+    pub out_of_range: Option<bool>,
+    /// Set when the node has an 'AnchorUiContainer' in 'AnchorUiContainerMode::Hide', and is outside of it
+    pub outside_container: Option<bool>,
+    /// Set while the node is in an 'AnchorClusterGroup', true when it is replaced by a cluster badge
+    pub clustered: Option<bool>,
+    /// Set while 'AnchorUiBudget' is active, true when the node did not fit in the budget
+    pub over_budget: Option<bool>,
+    /// Set while 'AnchorUiDeclutter' is active, true when the node was hidden because it overlapped others
+    pub decluttered: Option<bool>,
+    /// If the plugin set the 'Visibility' of the node to hidden, so it can be shown again once nothing hides it
+    pub hidden_by_plugin: bool,
+}
+
+impl Default for AnchorUiVisibility {
+    fn default() -> Self {
+        Self {
+            outside_camera: false,
+            anchor_visible: false,
+            target_in_view: None,
+            // so a node does not flash hidden for a frame before its layers are checked
+            on_camera_layers: true,
+            forced: None,
+            rules_hidden: None,
+            custom_hidden: false,
+            occluded: None,
+            out_of_range: None,
+            outside_container: None,
+            clustered: None,
+            over_budget: None,
+            decluttered: None,
+            hidden_by_plugin: false,
+        }
+    }
+}
+
+impl AnchorUiVisibility {
+    /// If the plugin should touch the 'Visibility' of this node at all, only when something of the plugin can hide it
+    ///
+    /// 'AnchorUiConfig::fade' and 'AnchorUiConfig::transition' only change how it is hidden, so they do not count
+    fn is_managed(&self) -> bool {
+        self.forced.is_some()
+            || self.rules_hidden.is_some()
+            || self.out_of_range.is_some()
+            || self.occluded.is_some()
+            || self.outside_container.is_some()
+            || self.clustered.is_some()
+            || self.over_budget.is_some()
+            || self.decluttered.is_some()
+    }
+
+    /// If the node should be hidden because of where its target is, regardless of other anchored nodes
+    pub(crate) fn hidden_by_target(&self) -> bool {
+        if let Some(forced) = self.forced {
+            return forced;
+        }
+        self.rules_hidden == Some(true)
+            || self.out_of_range == Some(true)
+            || self.occluded == Some(true)
+            || self.outside_container == Some(true)
+    }
 
-        let nodewidth = if let Val::Px(width) = node.width {
-            width
+    /// If the node should be hidden for any reason
+    fn is_hidden(&self) -> bool {
+        if let Some(forced) = self.forced {
+            return forced;
+        }
+        self.hidden_by_target()
+            || self.clustered == Some(true)
+            || self.over_budget == Some(true)
+            || self.decluttered == Some(true)
+    }
+}
+
+fn system_check_render_layers<C: Component>(
+    cameras: Query<Option<&RenderLayers>, With<C>>,
+    targets: Query<Option<&RenderLayers>>,
+    uinodes: Query<(&mut AnchorUiVisibility, &AnchorUiNode)>,
+) {
+    let camera_layers = match cameras.single() {
+        Ok(layers) => layers.cloned().unwrap_or_default(),
+        Err(QuerySingleError::NoEntities(_)) => return,
+        Err(err @ QuerySingleError::MultipleEntities(_)) => {
+            bevy::log::error!("more than one camera with the specified marker component: {err}");
+            return;
+        }
+    };
+    let default_layers = RenderLayers::default();
+    for (mut visibility, anchor) in uinodes {
+        // a target that can not be found is not hidden by its layers
+        let on_camera_layers = targets.get(anchor.target).map_or(true, |target_layers| {
+            camera_layers.intersects(target_layers.unwrap_or(&default_layers))
+        });
+        if visibility.on_camera_layers != on_camera_layers {
+            visibility.on_camera_layers = on_camera_layers;
+        }
+    }
+}
+
+fn system_follow_visibility<C: Component>(
+    cameras: Query<&VisibleEntities, With<C>>,
+    followed: Query<(&InheritedVisibility, Has<Aabb>)>,
+    uinodes: Query<(&mut AnchorUiVisibility, &AnchorUiNode, &AnchorUiConfig)>,
+) {
+    // 'ViewVisibility' would also be set by other cameras and by shadow casting lights
+    let in_camera: Option<HashSet<Entity>> = uinodes
+        .iter()
+        .any(|(_, _, config)| config.outside_camera_test == OutsideCameraTest::ViewVisibility)
+        .then(|| cameras.single().ok())
+        .flatten()
+        .map(|visible| visible.entities.values().flatten().copied().collect());
+    for (mut visibility, anchor, _) in uinodes {
+        if let Ok((followed_visibility, culled)) = followed.get(anchor.target) {
+            visibility.anchor_visible = followed_visibility.get();
+            let in_view = in_camera
+                .as_ref()
+                .filter(|_| culled)
+                .map(|in_camera| in_camera.contains(&anchor.target));
+            if visibility.target_in_view != in_view {
+                visibility.target_in_view = in_view;
+            }
+        }
+    }
+}
+
+fn system_update_visibility_of_uinode(
+    uinodes: Query<(
+        &mut Visibility,
+        &mut AnchorUiOpacity,
+        &mut AnchorUiAnimation,
+        &mut AnchorUiVisibility,
+        &AnchorUiConfig,
+    )>,
+    time: Res<Time>,
+) {
+    for (mut visibility, mut opacity, mut animation, mut anchor_vis, config) in uinodes {
+        if !anchor_vis.is_managed() {
+            // the plugin hid the node and nothing hides it anymore, hand it back visible
+            if anchor_vis.hidden_by_plugin {
+                anchor_vis.hidden_by_plugin = false;
+                visibility.set_if_neq(Visibility::Visible);
+            }
+            // nothing can hide the node, so it should not be stuck half faded or animated out
+            if opacity.fade != 1.0 {
+                opacity.fade = 1.0;
+            }
+            if animation.phase != AnchorAnimationPhase::Visible {
+                animation.set_visible(true);
+            }
+            continue;
+        }
+        let hidden = anchor_vis.is_hidden();
+        let mut visible = !hidden;
+
+        // stays visible until it has faded out, and until the exit animation is done
+        if let Some(fade) = &config.fade {
+            visible |= opacity::step_fade(&mut opacity, fade, hidden, time.delta_secs());
+        } else if opacity.fade != 1.0 {
+            opacity.fade = 1.0;
+        }
+        if let Some(transition) = &config.transition {
+            visible |= animation.step(transition, hidden, time.delta_secs());
         } else {
-            computed_node.size().x * computed_node.inverse_scale_factor()
+            animation.set_visible(!hidden);
+        }
+
+        if anchor_vis.hidden_by_plugin == visible {
+            anchor_vis.hidden_by_plugin = !visible;
+        }
+        if !visible {
+            visibility.set_if_neq(Visibility::Hidden);
+        } else {
+            visibility.set_if_neq(Visibility::Visible);
+        }
+    }
+}
+
+/// Orders anchored UI nodes for when the plugin has to choose between them,
+/// highest priority first, then closest to the camera, and the entity to keep it stable between frames
+pub(crate) fn sort_by_priority(candidates: &mut [(Entity, AnchorPriority, f32)]) {
+    candidates.sort_unstable_by(|(a, a_priority, a_depth), (b, b_priority, b_depth)| {
+        b_priority
+            .cmp(a_priority)
+            .then(a_depth.total_cmp(b_depth))
+            .then(a.cmp(b))
+    });
+}
+
+/// What 'system_limit_visible_ui_nodes' needs to rank an anchored UI node
+#[derive(QueryData)]
+#[query_data(mutable)]
+struct BudgetQuery {
+    entity: Entity,
+    node: &'static Node,
+    visibility: &'static mut AnchorUiVisibility,
+    projection: &'static AnchorUiProjection,
+    config: &'static AnchorUiConfig,
+    priority: Option<&'static AnchorPriority>,
+}
+
+fn system_limit_visible_ui_nodes(budget: Res<AnchorUiBudget>, mut uinodes: Query<BudgetQuery>) {
+    let mut candidates = Vec::new();
+    for mut uinode in uinodes.iter_mut() {
+        if uinode.node.display == Display::None
+            || uinode.visibility.hidden_by_target()
+            || uinode.visibility.clustered == Some(true)
+        {
+            uinode.visibility.over_budget = None;
+            continue;
+        }
+        candidates.push((
+            uinode.entity,
+            uinode.priority.copied().unwrap_or_default(),
+            uinode.projection.depth,
+        ));
+    }
+    sort_by_priority(&mut candidates);
+
+    for (rank, (entity, _, _)) in candidates.into_iter().enumerate() {
+        if let Ok(mut uinode) = uinodes.get_mut(entity) {
+            uinode.visibility.over_budget = Some(rank >= budget.max_visible);
+        }
+    }
+}
+
+/// Shows the UI nodes that were over the budget, once 'AnchorUiBudget' has been removed
+fn system_reset_visible_limit(uinodes: Query<&mut AnchorUiVisibility>) {
+    for mut visibility in uinodes {
+        if visibility.over_budget.is_some() {
+            visibility.over_budget = None;
+        }
+    }
+}
+
+/// Everything 'system_move_ui_nodes' reads and writes on an anchored UI node
+#[derive(QueryData)]
+#[query_data(mutable)]
+struct AnchoredNodeQuery {
+    entity: Entity,
+    node: &'static mut Node,
+    visibility: &'static mut AnchorUiVisibility,
+    active: &'static mut ActiveAnchorPoint,
+    projection: &'static mut AnchorUiProjection,
+    ui_transform: &'static mut UiTransform,
+    animation: &'static AnchorUiAnimation,
+    motion: &'static mut AnchorUiMotion,
+    fixed_history: &'static AnchorUiFixedHistory,
+    computed_node: &'static ComputedNode,
+    anchor: &'static AnchorUiNode,
+    config: &'static AnchorUiConfig,
+    container: Option<&'static AnchorUiContainer>,
+    retarget: Option<&'static mut AnchorUiRetarget>,
+}
+
+fn system_move_ui_nodes<C: Component>(
+    cameras: Query<(Entity, &Camera, Option<&Projection>), With<C>>,
+    window: Query<&Window, With<PrimaryWindow>>,
+    mut uinodes: Query<AnchoredNodeQuery>,
+    containers: Query<(&ComputedNode, &UiGlobalTransform)>,
+    transformhelper: TransformHelper,
+    time: Res<Time>,
+    fixed_time: Res<Time<Fixed>>,
+) {
+    let window = match window.single() {
+        Ok(window) => window,
+        Err(QuerySingleError::NoEntities(_)) => return,
+        Err(err @ QuerySingleError::MultipleEntities(_)) => {
+            bevy::log::error!("more than one primary window: {err}");
+            return;
+        }
+    };
+    let (camera_entity, main_camera, camera_projection) = match cameras.single() {
+        Ok(camera) => camera,
+        Err(QuerySingleError::NoEntities(_)) => return,
+        Err(err @ QuerySingleError::MultipleEntities(_)) => {
+            bevy::log::error!("more than one camera with the specified marker component: {err}");
+            return;
+        }
+    };
+    let Ok(main_camera_transform) = transformhelper.compute_global_transform(camera_entity) else {
+        warn!("Failed computing global transform for Camera Entity");
+        return;
+    };
+    let viewport = main_camera
+        .logical_viewport_rect()
+        .unwrap_or(Rect::from_corners(Vec2::ZERO, window.size()));
+
+    for AnchoredNodeQueryItem {
+        entity: uientity,
+        mut node,
+        visibility: mut vis,
+        mut active,
+        mut projection,
+        mut ui_transform,
+        animation,
+        mut motion,
+        fixed_history,
+        computed_node,
+        anchor: uinode,
+        config: uianchorconf,
+        container,
+        mut retarget,
+    } in uinodes.iter_mut()
+    {
+        if node.display == Display::None {
+            // The node is not displayed, skip it
+            continue;
+        }
+
+        // what location should we sync to
+        let interpolated = uianchorconf
+            .interpolate_fixed_update
+            .then(|| fixed_history.interpolate(uinode.target, fixed_time.overstep_fraction()))
+            .flatten();
+        let target_location = if let Some(interpolated) = interpolated {
+            interpolated
+        } else if let Ok(gt) = transformhelper.compute_global_transform(uinode.target) {
+            gt.translation()
+        } else {
+            warn!("AnchorTarget({}) failed to compute global transform, uinode: {uientity} will not be updated", uinode.target);
+            continue;
         };
-        let leftpos = match uianchorconf.anchorpoint.horizontal {
-            HorizontalAnchor::Left => Val::Px(position.x),
-            HorizontalAnchor::Mid => Val::Px(position.x - nodewidth / 2.0),
-            HorizontalAnchor::Right => Val::Px(position.x - nodewidth),
+
+        // the region of the screen the node is allowed to be placed in
+        let container_rect = container.and_then(|container| {
+            let (computed, transform) = containers.get(container.container).ok()?;
+            Some(Rect::from_center_size(
+                transform.translation * computed.inverse_scale_factor(),
+                computed.size() * computed.inverse_scale_factor(),
+            ))
+        });
+        let bounds = container_rect.unwrap_or(viewport);
+
+        let pixels_per_unit =
+            pixels_per_unit(main_camera, &main_camera_transform, target_location).unwrap_or(0.0);
+        if let Some(world_size) = uianchorconf.world_size {
+            if world_size.mode == WorldSizeMode::Resize && pixels_per_unit > 0.0 {
+                let width = Val::Px(world_size.width * pixels_per_unit);
+                if node.width != width {
+                    node.width = width;
+                }
+                if let Some(height) = world_size.height {
+                    let height = Val::Px(height * pixels_per_unit);
+                    if node.height != height {
+                        node.height = height;
+                    }
+                }
+            }
+        }
+
+        // the node is laid out at this size, and then scaled around its centre by 'UiTransform'
+        let layout_size = anchored_node_size(&node, computed_node);
+        let scale_at = |depth: f32| match uianchorconf.world_size {
+            Some(world_size) if pixels_per_unit > 0.0 => {
+                world_size.scale(pixels_per_unit, layout_size)
+            }
+            Some(_) => Vec2::ONE,
+            None => uianchorconf.distance_scaling.map_or(Vec2::ONE, |scaling| {
+                Vec2::splat(scaling.scale(camera_projection, depth))
+            }),
         };
 
-        // if check_if_not_close(node.as_ref().left, leftpos) {
-        node.left = leftpos;
-        // }
+        // where the node ends up on screen using one of the configured anchorpoints
+        let place_at = |target_location: Vec3, fallback: Option<usize>| -> Option<(Vec3, Rect)> {
+            let candidate = uianchorconf.candidate(fallback)?;
+            let world_location = target_location + candidate.offset.unwrap_or(Vec3::ZERO);
+            let position = main_camera
+                .world_to_viewport_with_depth(&main_camera_transform, world_location)
+                .ok()?;
+            let anchorpoint = match (fallback, uianchorconf.auto_anchor) {
+                (None, Some(auto_anchor)) => auto_anchor.anchorpoint(position.truncate(), bounds),
+                _ => candidate.anchorpoint,
+            };
+            let size = layout_size * scale_at(position.z);
+            Some((position, anchorpoint.rect_at(position.truncate(), size)))
+        };
+        let place = |fallback: Option<usize>| place_at(target_location, fallback);
 
-        let window_height = window.height();
+        if uianchorconf.fallbacks.is_empty() {
+            active.set_if_neq(ActiveAnchorPoint::default());
+        } else {
+            let chosen = choose_fallback(uianchorconf, active.fallback, bounds, |fallback| {
+                place(fallback).map(|(_, rect)| rect)
+            });
+            active.set_if_neq(ActiveAnchorPoint { fallback: chosen });
+        }
 
-        let nodeheight = if let Val::Px(height) = node.height {
-            height
+        let Some((position, mut rect)) = place(active.fallback) else {
+            // Object is offscreen
+            bevy::log::debug!("world location is offscreen, and thus we dont change the position");
+            vis.outside_camera = true;
+            motion.reset();
+            continue;
+        };
+
+        vis.outside_camera = false;
+
+        let out_of_range = uianchorconf
+            .distance_culling
+            .map(|culling| culling.is_out_of_range(position.z, vis.out_of_range == Some(true)));
+        if vis.out_of_range != out_of_range {
+            vis.out_of_range = out_of_range;
+        }
+
+        if let Some(retarget) = retarget.as_mut() {
+            retarget.elapsed += time.delta_secs();
+            let from = transformhelper
+                .compute_global_transform(retarget.from)
+                .ok()
+                .and_then(|gt| place_at(gt.translation(), active.fallback));
+            if let Some((_, from_rect)) = from {
+                let min = from_rect.min.lerp(rect.min, retarget.progress());
+                rect = Rect::from_corners(min, min + rect.size());
+            } else {
+                // the previous target is gone, or behind the camera
+                retarget.elapsed = retarget.duration;
+            }
+        }
+
+        if let Some(prediction) = &uianchorconf.prediction {
+            let offset = motion.predict(
+                prediction,
+                position.truncate(),
+                time.elapsed_secs(),
+                time.delta_secs(),
+            );
+            rect = Rect::from_corners(rect.min + offset, rect.max + offset);
+        } else if !motion.samples.is_empty() {
+            motion.samples.clear();
+        }
+
+        if let Some(smoothing) = &uianchorconf.smoothing {
+            let min = motion.smooth(smoothing, rect.min, time.delta_secs());
+            rect = Rect::from_corners(min, min + rect.size());
         } else {
-            computed_node.size().y * computed_node.inverse_scale_factor()
+            motion.reset_smoothing();
+        }
+
+        let outside_container = match (container, container_rect) {
+            (Some(container), Some(container_rect)) => match container.mode {
+                AnchorUiContainerMode::Hide => Some(!container_rect.contains(position.truncate())),
+                AnchorUiContainerMode::Clamp => {
+                    rect = clamp_rect(rect, container_rect);
+                    None
+                }
+            },
+            // the container has not been laid out yet, hide until it has
+            (Some(container), None) => {
+                (container.mode == AnchorUiContainerMode::Hide).then_some(true)
+            }
+            (None, _) => None,
         };
+        if vis.outside_container != outside_container {
+            vis.outside_container = outside_container;
+        }
 
-        let newheight = match uianchorconf.anchorpoint.vertical {
-            VerticalAnchor::Top => Val::Px(window_height - position.y - nodeheight),
-            VerticalAnchor::Mid => Val::Px(window_height - position.y - nodeheight / 2.0),
-            VerticalAnchor::Bottom => Val::Px(window_height - position.y),
+        if uianchorconf.pixel_snapping {
+            let scale_factor = window.scale_factor();
+            let min = (rect.min * scale_factor).round() / scale_factor;
+            rect = Rect::from_corners(min, min + rect.size());
+        }
+
+        if node.as_ref().position_type != PositionType::Absolute {
+            node.position_type = PositionType::Absolute;
+        }
+
+        let scale = scale_at(position.z);
+        let size = layout_size * scale;
+        let inset = (size - layout_size) / 2.0;
+        let window_height = window.height();
+        let left = rect.min.x + inset.x;
+        let bottom = window_height - rect.max.y + inset.y;
+
+        match node.as_ref().left {
+            Val::Px(current) if !is_outside_dead_zone(current, left, uianchorconf.dead_zone) => {
+                rect.min.x = current - inset.x;
+            }
+            // only marked as changed once the final position is known, see 'system_mark_moved_ui_nodes'
+            _ => node.bypass_change_detection().left = Val::Px(left),
+        }
+        match node.as_ref().bottom {
+            Val::Px(current) if !is_outside_dead_zone(current, bottom, uianchorconf.dead_zone) => {
+                rect.min.y = window_height - current + inset.y - size.y;
+            }
+            _ => node.bypass_change_detection().bottom = Val::Px(bottom),
+        }
+        // keep the rect in sync with where the node actually is
+        let rect = Rect::from_corners(rect.min, rect.min + size);
+
+        let world_position = target_location
+            + uianchorconf
+                .candidate(active.fallback)
+                .and_then(|candidate| candidate.offset)
+                .unwrap_or(Vec3::ZERO);
+        let target_position = main_camera
+            .world_to_viewport(&main_camera_transform, target_location)
+            .unwrap_or(position.truncate());
+        projection.set_if_neq(AnchorUiProjection {
+            position: position.truncate(),
+            target_position,
+            world_position,
+            depth: position.z,
+            rect,
+            scale,
+            pixels_per_unit,
+        });
+        // before layout, so the scaled node is drawn where it was placed this frame
+        animation::write_ui_transform(&mut ui_transform, animation, scale, uianchorconf);
+    }
+}
+
+/// How many logical pixels one world unit covers at 'world_location', measured across the view
+fn pixels_per_unit(
+    camera: &Camera,
+    camera_transform: &GlobalTransform,
+    world_location: Vec3,
+) -> Option<f32> {
+    let position = camera
+        .world_to_viewport(camera_transform, world_location)
+        .ok()?;
+    let across = camera
+        .world_to_viewport(camera_transform, world_location + *camera_transform.right())
+        .ok()?;
+    Some(position.distance(across))
+}
+
+/// Only move if the change in position is more than 'dead_zone' logical pixels, stops vibrations
+fn is_outside_dead_zone(current: f32, new: f32, dead_zone: f32) -> bool {
+    if current == new {
+        return false;
+    }
+    (current - new).abs() > dead_zone
+}
+
+fn system_finish_retargets(mut commands: Commands, uinodes: Query<(Entity, &AnchorUiRetarget)>) {
+    for (entity, retarget) in uinodes.iter() {
+        if retarget.is_finished() {
+            commands.entity(entity).remove::<AnchorUiRetarget>();
+        }
+    }
+}
+
+/// Moves an already placed anchored UI node by 'offset' logical pixels, keeping its 'AnchorUiProjection' in sync
+pub(crate) fn move_placed_node(node: &mut Node, projection: &mut AnchorUiProjection, offset: Vec2) {
+    if let Val::Px(left) = &mut node.left {
+        *left += offset.x;
+    }
+    if let Val::Px(bottom) = &mut node.bottom {
+        *bottom -= offset.y;
+    }
+    projection.rect =
+        Rect::from_corners(projection.rect.min + offset, projection.rect.max + offset);
+}
+
+/// Marks the UI nodes whose position changed this frame, once 'MoveUiNodes', 'AnchorUiStack' and 'AnchorUiDeclutter'
+/// are done moving them, so nodes that end up where they were are not laid out again
+fn system_mark_moved_ui_nodes(uinodes: Query<(&mut Node, &mut AnchorUiMotion)>) {
+    for (mut node, mut motion) in uinodes {
+        let (Val::Px(left), Val::Px(bottom)) = (node.left, node.bottom) else {
+            continue;
         };
+        let placed = Some(Vec2::new(left, bottom));
+        if motion.laid_out != placed {
+            motion.laid_out = placed;
+            node.set_changed();
+        }
+    }
+}
+
+/// The size of the anchored node in logical pixels, preferring an explicitly set size over last frames computed one
+fn anchored_node_size(node: &Node, computed_node: &ComputedNode) -> Vec2 {
+    let computed = computed_node.size() * computed_node.inverse_scale_factor();
+    let width = if let Val::Px(width) = node.width {
+        width
+    } else {
+        computed.x
+    };
+    let height = if let Val::Px(height) = node.height {
+        height
+    } else {
+        computed.y
+    };
+    Vec2::new(width, height)
+}
+
+/// Moves 'rect' the shortest distance needed to be inside 'bounds', aligning it to the top left of 'bounds' if it is too large to fit
+fn clamp_rect(rect: Rect, bounds: Rect) -> Rect {
+    let max = (bounds.max - rect.size()).max(bounds.min);
+    let min = rect.min.clamp(bounds.min, max);
+    Rect::from_corners(min, min + rect.size())
+}
 
-        // if check_if_not_close(node.as_ref().bottom, newheight) {
-        node.bottom = newheight;
-        // }
+/// Picks the first anchorpoint candidate whose rect fits inside the viewport
+///
+/// Candidates before the currently active one must fit with 'fallback_hysteresis' to spare,
+/// so that a node right at the edge does not flip back and forth every frame
+fn choose_fallback(
+    config: &AnchorUiConfig,
+    active: Option<usize>,
+    viewport: Rect,
+    place: impl Fn(Option<usize>) -> Option<Rect>,
+) -> Option<usize> {
+    let active = active.filter(|index| *index < config.fallbacks.len());
+    let rank = |fallback: Option<usize>| fallback.map_or(0, |index| index + 1);
+    let shrunk = viewport.inflate(-config.fallback_hysteresis.max(0.0));
+
+    let candidates = std::iter::once(None).chain((0..config.fallbacks.len()).map(Some));
+    for candidate in candidates {
+        let Some(rect) = place(candidate) else {
+            continue;
+        };
+        let bounds = if rank(candidate) < rank(active) {
+            shrunk
+        } else {
+            viewport
+        };
+        if bounds.contains(rect.min) && bounds.contains(rect.max) {
+            return candidate;
+        }
     }
+    active
 }
 
-// // only move if the change position is more than one pixel from each other, stops vibrations
-// fn check_if_not_close(a: Val, b: Val) -> bool {
-//     if a == b {
-//         return false;
-//     }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A headless app with the plugin, without a window or camera, so UI nodes are not moved
+    pub(crate) fn test_app() -> App {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, TransformPlugin))
+            .add_plugins(AnchorUiPlugin::<Camera>::new());
+        app
+    }
+
+    /// Spawns a target and a UI node anchored to it
+    pub(crate) fn spawn_anchored(app: &mut App, bundle: impl Bundle) -> Entity {
+        let target = app.world_mut().spawn(Transform::default()).id();
+        app.world_mut()
+            .spawn((AnchorUiNode::to_entity(target), bundle))
+            .id()
+    }
+
+    pub(crate) fn visibility(app: &App, entity: Entity) -> Visibility {
+        *app.world().get::<Visibility>(entity).unwrap()
+    }
+
+    #[test]
+    fn distance_culling_hides_outside_the_range() {
+        let culling = AnchorDistanceCulling::new(1.0, 10.0);
+        assert!(culling.is_out_of_range(0.5, false));
+        assert!(!culling.is_out_of_range(5.0, false));
+        assert!(culling.is_out_of_range(10.5, false));
+    }
+
+    #[test]
+    fn distance_culling_shows_only_past_the_hysteresis() {
+        let culling = AnchorDistanceCulling::new(1.0, 10.0).with_hysteresis(1.0);
+        // a visible UI node is hidden right at the edge
+        assert!(!culling.is_out_of_range(9.5, false));
+        assert!(culling.is_out_of_range(10.5, false));
+        // a hidden one has to come back past the margin
+        assert!(culling.is_out_of_range(9.5, true));
+        assert!(!culling.is_out_of_range(8.5, true));
+        assert!(culling.is_out_of_range(1.5, true));
+        assert!(!culling.is_out_of_range(2.5, true));
+    }
+
+    #[test]
+    fn auto_anchor_points_away_from_the_edges() {
+        let auto_anchor = AutoAnchor::new(Vec2::splat(0.2));
+        let anchorpoint = |position: Vec2| auto_anchor.anchorpoint(position, viewport());
+        assert_eq!(
+            anchorpoint(Vec2::new(10.0, 10.0)),
+            AnchorPoint::bottomright()
+        );
+        assert_eq!(anchorpoint(Vec2::new(90.0, 90.0)), AnchorPoint::topleft());
+        assert_eq!(
+            anchorpoint(Vec2::new(50.0, 50.0)),
+            AnchorPoint {
+                horizontal: HorizontalAnchor::Mid,
+                vertical: VerticalAnchor::Mid,
+            }
+        );
+        // inside the dead zone horizontally, near the bottom edge
+        assert_eq!(
+            anchorpoint(Vec2::new(55.0, 90.0)),
+            AnchorPoint {
+                horizontal: HorizontalAnchor::Mid,
+                vertical: VerticalAnchor::Top,
+            }
+        );
+    }
+
+    #[test]
+    fn world_size_scales_to_width_and_height() {
+        let layout_size = Vec2::new(50.0, 25.0);
+        assert_eq!(
+            AnchorWorldSize::new(2.0).scale(50.0, layout_size),
+            Vec2::new(2.0, 2.0)
+        );
+        let stretched = AnchorWorldSize::new(2.0).with_height(2.0);
+        assert_eq!(stretched.scale(50.0, layout_size), Vec2::new(2.0, 4.0));
+        assert_eq!(
+            stretched
+                .with_scale_range(0.5, 3.0)
+                .scale(50.0, layout_size),
+            Vec2::new(2.0, 3.0)
+        );
+    }
+
+    #[test]
+    fn world_size_leaves_resized_and_unlaid_nodes_unscaled() {
+        let resized = AnchorWorldSize::new(2.0).with_mode(WorldSizeMode::Resize);
+        assert_eq!(resized.scale(50.0, Vec2::new(50.0, 25.0)), Vec2::ONE);
+        assert_eq!(AnchorWorldSize::new(2.0).scale(50.0, Vec2::ZERO), Vec2::ONE);
+    }
+
+    #[test]
+    fn removing_hidden_override_shows_node() {
+        let mut app = test_app();
+        let uinode = spawn_anchored(&mut app, AnchorUiVisibilityOverride::Hidden);
+        app.update();
+        assert_eq!(visibility(&app, uinode), Visibility::Hidden);
+
+        app.world_mut()
+            .entity_mut(uinode)
+            .remove::<AnchorUiVisibilityOverride>();
+        app.update();
+        assert_eq!(visibility(&app, uinode), Visibility::Visible);
+    }
 
-//     match (a, b) {
-//         (Val::Px(a), Val::Px(b)) => (a - b).abs() > 1.0, // If they are more than a pixel from eachother
-//         _ => true,
-//     }
-// }
+    #[test]
+    fn removing_budget_shows_nodes_again() {
+        let mut app = test_app();
+        app.insert_resource(AnchorUiBudget::new(0));
+        let uinode = spawn_anchored(&mut app, ());
+        app.update();
+        assert_eq!(visibility(&app, uinode), Visibility::Hidden);
+
+        app.world_mut().remove_resource::<AnchorUiBudget>();
+        app.update();
+        assert_eq!(visibility(&app, uinode), Visibility::Visible);
+    }
+
+    #[test]
+    fn fixed_history_restarts_on_new_target() {
+        let mut app = test_app();
+        let first = app.world_mut().spawn(Transform::default()).id();
+        let second = app
+            .world_mut()
+            .spawn(Transform::from_xyz(10.0, 0.0, 0.0))
+            .id();
+        let config = AnchorUiConfig::default().with_fixed_update_interpolation();
+        let uinode = app
+            .world_mut()
+            .spawn((AnchorUiNode::to_entity(first), config))
+            .id();
+        app.world_mut().run_schedule(FixedPostUpdate);
+        app.world_mut().run_schedule(FixedPostUpdate);
+
+        app.world_mut()
+            .entity_mut(uinode)
+            .insert(AnchorUiNode::to_entity(second));
+        // not recorded for the new target yet
+        let history = app.world().get::<AnchorUiFixedHistory>(uinode).unwrap();
+        assert_eq!(history.interpolate(second, 0.5), None);
+
+        app.world_mut().run_schedule(FixedPostUpdate);
+        let history = app.world().get::<AnchorUiFixedHistory>(uinode).unwrap();
+        assert_eq!(
+            history.interpolate(second, 0.5),
+            Some(Vec3::new(10.0, 0.0, 0.0))
+        );
+    }
+
+    #[test]
+    fn unmanaged_node_keeps_its_own_visibility() {
+        let mut app = test_app();
+        let uinode = spawn_anchored(&mut app, Visibility::Hidden);
+        app.update();
+        app.update();
+        assert_eq!(visibility(&app, uinode), Visibility::Hidden);
+    }
+
+    fn viewport() -> Rect {
+        Rect::new(0.0, 0.0, 100.0, 100.0)
+    }
+
+    fn config(hysteresis: f32) -> AnchorUiConfig {
+        AnchorUiConfig::default()
+            .with_fallback(AnchorPoint::topleft())
+            .with_fallback(AnchorPoint::bottomright())
+            .with_fallback_hysteresis(hysteresis)
+    }
+
+    #[test]
+    fn choose_fallback_prefers_primary_when_it_fits() {
+        let chosen = choose_fallback(&config(0.0), Some(1), viewport(), |_| {
+            Some(Rect::new(10.0, 10.0, 20.0, 20.0))
+        });
+        assert_eq!(chosen, None);
+    }
+
+    #[test]
+    fn choose_fallback_uses_first_fitting_fallback() {
+        let chosen = choose_fallback(&config(0.0), None, viewport(), |fallback| match fallback {
+            Some(1) => Some(Rect::new(10.0, 10.0, 20.0, 20.0)),
+            _ => Some(Rect::new(90.0, 90.0, 110.0, 110.0)),
+        });
+        assert_eq!(chosen, Some(1));
+    }
+
+    #[test]
+    fn choose_fallback_keeps_active_when_nothing_fits() {
+        let chosen = choose_fallback(&config(0.0), Some(0), viewport(), |_| {
+            Some(Rect::new(-10.0, -10.0, 200.0, 200.0))
+        });
+        assert_eq!(chosen, Some(0));
+
+        let chosen = choose_fallback(&config(0.0), None, viewport(), |_| None);
+        assert_eq!(chosen, None);
+    }
+
+    #[test]
+    fn choose_fallback_returns_only_outside_the_hysteresis_margin() {
+        // the primary fits the viewport, but is within 5 pixels of its edge
+        let place = |fallback: Option<usize>| match fallback {
+            None => Some(Rect::new(2.0, 10.0, 20.0, 20.0)),
+            _ => Some(Rect::new(40.0, 40.0, 50.0, 50.0)),
+        };
+        assert_eq!(
+            choose_fallback(&config(5.0), Some(0), viewport(), place),
+            Some(0)
+        );
+        // without an active fallback there is nothing to stick to
+        assert_eq!(choose_fallback(&config(5.0), None, viewport(), place), None);
+
+        // far enough inside to switch back
+        let place = |fallback: Option<usize>| match fallback {
+            None => Some(Rect::new(6.0, 10.0, 20.0, 20.0)),
+            _ => Some(Rect::new(40.0, 40.0, 50.0, 50.0)),
+        };
+        assert_eq!(
+            choose_fallback(&config(5.0), Some(0), viewport(), place),
+            None
+        );
+    }
+}
@@ -0,0 +1,330 @@
+use bevy::{
+    camera::primitives::Aabb,
+    ecs::{
+        query::QuerySingleError,
+        system::{StaticSystemParam, SystemParam},
+    },
+    prelude::*,
+};
+
+use crate::{
+    AnchorUiConfig, AnchorUiNode, AnchorUiOpacity, AnchorUiProjection, AnchorUiVisibility,
+};
+
+/// Marks an entity that hides the anchored UI nodes behind it, using its 'Aabb' and 'GlobalTransform'
+///
+/// Meshes get their 'Aabb' from bevy, for other entities it has to be inserted
+#[derive(Component, Reflect, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AnchorOccluder;
+
+/// What happens to an anchored UI node while its target is occluded, or partially occluded
+#[derive(Default, Reflect, Debug, Clone, Copy, PartialEq)]
+pub enum OcclusionMode {
+    /// The UI node is hidden while its target is fully occluded
+    #[default]
+    Hide,
+    /// The UI node is drawn with this opacity while its target is fully occluded, and in between while partially occluded
+    Dim(f32),
+}
+
+/// Checks if something is in between the camera and the anchored world location of the UI node
+///
+/// The check is done by the 'AnchorOcclusionProvider' of the plugin, by default 'AabbOcclusion'
+#[derive(Reflect, Debug, Clone, Copy, PartialEq, Default)]
+pub struct AnchorOcclusion {
+    pub mode: OcclusionMode,
+    /// Seconds in between two checks, 0.0 checks every frame
+    pub interval: f32,
+}
+
+impl AnchorOcclusion {
+    /// Hides the UI node while its target is occluded
+    pub fn hide() -> Self {
+        Self {
+            mode: OcclusionMode::Hide,
+            interval: 0.0,
+        }
+    }
+    /// Draws the UI node with 'opacity' while its target is occluded
+    pub fn dim(opacity: f32) -> Self {
+        Self {
+            mode: OcclusionMode::Dim(opacity),
+            interval: 0.0,
+        }
+    }
+    pub fn with_interval(mut self, interval: f32) -> Self {
+        self.interval = interval;
+        self
+    }
+}
+
+/// How much of the target an 'AnchorOcclusionProvider' found to be hidden from the camera
+#[derive(Default, Reflect, Debug, Clone, Copy, PartialEq)]
+pub enum Occlusion {
+    #[default]
+    Visible,
+    /// Part of the target is hidden, with the fraction from 0.0 to 1.0 that is still visible
+    Partial(f32),
+    Occluded,
+}
+
+impl Occlusion {
+    /// How much of the target is visible, from 0.0 to 1.0
+    pub fn visible_fraction(&self) -> f32 {
+        match self {
+            Occlusion::Visible => 1.0,
+            Occlusion::Partial(fraction) => fraction.clamp(0.0, 1.0),
+            Occlusion::Occluded => 0.0,
+        }
+    }
+}
+
+/// The result of the last occlusion check of an anchored UI node
+#[derive(Component, Reflect, Debug, Clone, Copy, PartialEq, Default)]
+pub struct AnchorUiOcclusion {
+    pub occlusion: Occlusion,
+    /// Seconds since the last check, 'None' until the first one
+    since_check: Option<f32>,
+}
+
+/// The line of sight from the camera to an anchored UI node, that an 'AnchorOcclusionProvider' checks
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OcclusionRay {
+    /// The anchored UI node
+    pub uinode: Entity,
+    /// The entity the UI node is anchored to, which should not occlude itself
+    pub target: Entity,
+    /// Where the ray starts, at the camera
+    pub origin: Vec3,
+    /// The anchored world location, where the ray ends
+    pub end: Vec3,
+}
+
+/// Decides if anchored UI nodes are occluded, the plugin calls it for every UI node with 'AnchorUiConfig::occlusion' that is in view
+///
+/// Implement it for a 'SystemParam', such as one that wraps the raycasts of a physics engine, and
+/// pass it to 'AnchorUiPlugin::with_occlusion_provider'. The plugin uses 'AabbOcclusion' by default
+pub trait AnchorOcclusionProvider {
+    fn occlusion(&self, ray: &OcclusionRay) -> Occlusion;
+}
+
+/// The built in 'AnchorOcclusionProvider', which casts rays against the 'Aabb' of every 'AnchorOccluder' on the CPU
+///
+/// Keep the number of occluders and the check frequency reasonable
+#[derive(SystemParam)]
+pub struct AabbOcclusion<'w, 's> {
+    occluders:
+        Query<'w, 's, (Entity, &'static Aabb, &'static GlobalTransform), With<AnchorOccluder>>,
+}
+
+impl AnchorOcclusionProvider for AabbOcclusion<'_, '_> {
+    fn occlusion(&self, ray: &OcclusionRay) -> Occlusion {
+        let occluded = self.occluders.iter().any(|(entity, aabb, transform)| {
+            entity != ray.target && segment_hits_aabb(ray.origin, ray.end, aabb, transform)
+        });
+        if occluded {
+            Occlusion::Occluded
+        } else {
+            Occlusion::Visible
+        }
+    }
+}
+
+/// If the segment from 'start' to 'end' passes through 'aabb', placed by 'transform'
+fn segment_hits_aabb(start: Vec3, end: Vec3, aabb: &Aabb, transform: &GlobalTransform) -> bool {
+    let inverse = transform.affine().inverse();
+    let start = inverse.transform_point3(start);
+    let end = inverse.transform_point3(end);
+    let delta = end - start;
+    let min = Vec3::from(aabb.min());
+    let max = Vec3::from(aabb.max());
+
+    // stop just short of the end, so an occluder the anchored location rests on does not count
+    let mut t_min = 0.0_f32;
+    let mut t_max = 0.999_f32;
+    for axis in 0..3 {
+        if delta[axis].abs() <= f32::EPSILON {
+            if start[axis] < min[axis] || start[axis] > max[axis] {
+                return false;
+            }
+            continue;
+        }
+        let (t0, t1) = (
+            (min[axis] - start[axis]) / delta[axis],
+            (max[axis] - start[axis]) / delta[axis],
+        );
+        t_min = t_min.max(t0.min(t1));
+        t_max = t_max.min(t0.max(t1));
+        if t_min > t_max {
+            return false;
+        }
+    }
+    true
+}
+
+pub(crate) fn system_check_occlusion<C: Component, P: SystemParam>(
+    cameras: Query<(&GlobalTransform, Option<&Projection>), With<C>>,
+    provider: StaticSystemParam<P>,
+    uinodes: Query<(
+        Entity,
+        &mut AnchorUiOcclusion,
+        &mut AnchorUiVisibility,
+        &mut AnchorUiOpacity,
+        &AnchorUiProjection,
+        &AnchorUiNode,
+        &AnchorUiConfig,
+    )>,
+    time: Res<Time>,
+) where
+    for<'w, 's> P::Item<'w, 's>: AnchorOcclusionProvider,
+{
+    let (camera_transform, camera_projection) = match cameras.single() {
+        Ok(camera) => camera,
+        Err(QuerySingleError::NoEntities(_)) => return,
+        Err(err @ QuerySingleError::MultipleEntities(_)) => {
+            bevy::log::error!("more than one camera with the specified marker component: {err}");
+            return;
+        }
+    };
+
+    for (entity, mut occlusion, mut vis, mut opacity, projection, anchor, config) in uinodes {
+        let Some(settings) = &config.occlusion else {
+            if vis.occluded.is_some() {
+                vis.occluded = None;
+            }
+            if opacity.occlusion != 1.0 {
+                opacity.occlusion = 1.0;
+            }
+            continue;
+        };
+
+        let since_check = occlusion.since_check.map(|since| since + time.delta_secs());
+        match since_check {
+            Some(since) if since < settings.interval => {
+                occlusion.since_check = Some(since);
+            }
+            _ if vis.outside_camera => {}
+            _ => {
+                let end = projection.world_position;
+                // orthographic cameras look along parallel rays
+                let start = match camera_projection {
+                    Some(Projection::Orthographic(_)) => {
+                        end - *camera_transform.forward() * projection.depth
+                    }
+                    _ => camera_transform.translation(),
+                };
+                let result = provider.occlusion(&OcclusionRay {
+                    uinode: entity,
+                    target: anchor.target,
+                    origin: start,
+                    end,
+                });
+                occlusion.set_if_neq(AnchorUiOcclusion {
+                    occlusion: result,
+                    since_check: Some(0.0),
+                });
+            }
+        }
+
+        let (occluded, dimmed) = match settings.mode {
+            OcclusionMode::Hide => (Some(occlusion.occlusion == Occlusion::Occluded), 1.0),
+            // partially occluded targets are dimmed part of the way
+            OcclusionMode::Dim(dim) => (
+                None,
+                dim.clamp(0.0, 1.0)
+                    .lerp(1.0, occlusion.occlusion.visible_fraction()),
+            ),
+        };
+        if vis.occluded != occluded {
+            vis.occluded = occluded;
+        }
+        if opacity.occlusion != dimmed {
+            opacity.occlusion = dimmed;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit_box() -> Aabb {
+        Aabb::from_min_max(Vec3::splat(-1.0), Vec3::splat(1.0))
+    }
+
+    #[test]
+    fn segment_through_the_box_hits() {
+        let transform = GlobalTransform::IDENTITY;
+        assert!(segment_hits_aabb(
+            Vec3::new(0.0, 0.0, 5.0),
+            Vec3::new(0.0, 0.0, -5.0),
+            &unit_box(),
+            &transform
+        ));
+    }
+
+    #[test]
+    fn segment_parallel_to_an_axis() {
+        let transform = GlobalTransform::IDENTITY;
+        // only moves along z, inside the box on x and y
+        assert!(segment_hits_aabb(
+            Vec3::new(0.5, 0.5, 5.0),
+            Vec3::new(0.5, 0.5, -5.0),
+            &unit_box(),
+            &transform
+        ));
+        // only moves along z, outside the box on x
+        assert!(!segment_hits_aabb(
+            Vec3::new(2.0, 0.0, 5.0),
+            Vec3::new(2.0, 0.0, -5.0),
+            &unit_box(),
+            &transform
+        ));
+    }
+
+    #[test]
+    fn segment_ending_on_the_box_does_not_hit() {
+        let transform = GlobalTransform::IDENTITY;
+        assert!(!segment_hits_aabb(
+            Vec3::new(0.0, 0.0, 5.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            &unit_box(),
+            &transform
+        ));
+        assert!(!segment_hits_aabb(
+            Vec3::new(0.0, 0.0, 5.0),
+            Vec3::new(0.0, 0.0, 2.0),
+            &unit_box(),
+            &transform
+        ));
+    }
+
+    #[test]
+    fn segment_against_rotated_and_scaled_box() {
+        // three units long along x, then turned to lie along z
+        let transform = GlobalTransform::from(
+            Transform::from_xyz(10.0, 0.0, 0.0)
+                .with_rotation(Quat::from_rotation_y(std::f32::consts::FRAC_PI_2))
+                .with_scale(Vec3::new(3.0, 1.0, 1.0)),
+        );
+        assert!(segment_hits_aabb(
+            Vec3::new(5.0, 0.0, 2.5),
+            Vec3::new(15.0, 0.0, 2.5),
+            &unit_box(),
+            &transform
+        ));
+        // would pass through the box if it was not rotated
+        assert!(!segment_hits_aabb(
+            Vec3::new(12.0, 0.0, 5.0),
+            Vec3::new(12.0, 0.0, -5.0),
+            &unit_box(),
+            &transform
+        ));
+        assert!(!segment_hits_aabb(
+            Vec3::new(10.0, 1.5, 5.0),
+            Vec3::new(10.0, 1.5, -5.0),
+            &unit_box(),
+            &transform
+        ));
+    }
+}
@@ -0,0 +1,416 @@
+#[cfg(feature = "distance_fog")]
+use bevy::pbr::{DistanceFog, FogFalloff};
+use bevy::{ecs::query::QueryData, prelude::*};
+
+use crate::{AnchorUiConfig, AnchorUiProjection, LeaderLineSegments};
+
+/// Fades anchored UI nodes in and out, instead of toggling their 'Visibility' instantly
+///
+/// The UI node is only set to 'Visibility::Hidden' once it has faded out completely
+#[derive(Reflect, Debug, Clone, Copy, PartialEq)]
+pub struct AnchorFade {
+    /// Seconds it takes to fade in from fully transparent
+    pub fade_in: f32,
+    /// Seconds it takes to fade out from fully opaque
+    pub fade_out: f32,
+}
+
+impl Default for AnchorFade {
+    fn default() -> Self {
+        Self::new(0.2)
+    }
+}
+
+impl AnchorFade {
+    /// Fades in and out over 'duration' seconds
+    pub fn new(duration: f32) -> Self {
+        Self {
+            fade_in: duration,
+            fade_out: duration,
+        }
+    }
+}
+
+/// Fades anchored UI nodes out as they get further from the camera, like fog does to the rest of the scene
+#[derive(Reflect, Debug, Clone, Copy, PartialEq)]
+pub struct AnchorDepthFog {
+    /// Distance from the camera where the UI node starts fading
+    pub near: f32,
+    /// Distance from the camera where the UI node has faded to 'min_opacity'
+    pub far: f32,
+    /// How the fog thickens from 'near' to 'far'
+    pub curve: EaseFunction,
+    /// The opacity the UI node keeps at 'far' and beyond
+    pub min_opacity: f32,
+    /// Uses the 'DistanceFog' of the camera instead of 'near', 'far' and 'curve', when the camera has one
+    ///
+    /// Only has an effect with the 'distance_fog' feature
+    pub use_camera_fog: bool,
+}
+
+impl Default for AnchorDepthFog {
+    fn default() -> Self {
+        Self::new(10.0, 50.0)
+    }
+}
+
+impl AnchorDepthFog {
+    /// Fades linearly from fully opaque at 'near' to fully transparent at 'far'
+    pub fn new(near: f32, far: f32) -> Self {
+        Self {
+            near,
+            far,
+            curve: EaseFunction::Linear,
+            min_opacity: 0.0,
+            use_camera_fog: false,
+        }
+    }
+    pub fn with_curve(mut self, curve: EaseFunction) -> Self {
+        self.curve = curve;
+        self
+    }
+    pub fn with_min_opacity(mut self, min_opacity: f32) -> Self {
+        self.min_opacity = min_opacity;
+        self
+    }
+    /// Follows the 'DistanceFog' of the camera, when it has one and the 'distance_fog' feature is enabled
+    pub fn with_camera_fog(mut self) -> Self {
+        self.use_camera_fog = true;
+        self
+    }
+
+    /// How thick the fog is at 'depth', from 0.0 (none) to 1.0 (fully fogged)
+    pub fn amount(&self, depth: f32) -> f32 {
+        let range = self.far - self.near;
+        let t = if range <= 0.0 {
+            if depth >= self.far {
+                1.0
+            } else {
+                0.0
+            }
+        } else {
+            ((depth - self.near) / range).clamp(0.0, 1.0)
+        };
+        self.curve.sample_clamped(t)
+    }
+
+    /// The opacity of a UI node with the given fog amount
+    fn opacity(&self, amount: f32) -> f32 {
+        let min_opacity = self.min_opacity.clamp(0.0, 1.0);
+        1.0 - amount.clamp(0.0, 1.0) * (1.0 - min_opacity)
+    }
+}
+
+/// How thick the 'DistanceFog' of a camera is at 'distance', from 0.0 (none) to 1.0 (fully fogged)
+#[cfg(feature = "distance_fog")]
+fn camera_fog_amount(fog: &DistanceFog, distance: f32) -> f32 {
+    let distance = distance.max(0.0);
+    let amount = match fog.falloff {
+        FogFalloff::Linear { start, end } => {
+            if end <= start {
+                if distance >= end {
+                    1.0
+                } else {
+                    0.0
+                }
+            } else {
+                (distance - start) / (end - start)
+            }
+        }
+        FogFalloff::Exponential { density } => 1.0 - (-distance * density).exp(),
+        FogFalloff::ExponentialSquared { density } => 1.0 - (-(distance * density).powi(2)).exp(),
+        FogFalloff::Atmospheric { extinction, .. } => {
+            let extinction = (extinction.x + extinction.y + extinction.z) / 3.0;
+            1.0 - (-distance * extinction).exp()
+        }
+    };
+    // a transparent fog color hides less
+    amount.clamp(0.0, 1.0) * fog.color.alpha()
+}
+
+/// The opacity the plugin applies to an anchored UI node and all of its descendants
+///
+/// It is multiplied into the alpha of their 'BackgroundColor', 'BorderColor', 'TextColor' and 'ImageNode', and into its leader line,
+/// whose own alpha is restored once the UI node is fully opaque again
+#[derive(Component, Reflect, Debug, Clone, Copy, PartialEq)]
+pub struct AnchorUiOpacity {
+    /// Opacity from 'AnchorFade'
+    pub(crate) fade: f32,
+    /// Opacity from 'AnchorDepthFog'
+    pub(crate) fog: f32,
+    /// Opacity from 'OcclusionMode::Dim'
+    pub(crate) occlusion: f32,
+    /// The opacity that was last applied to the descendants
+    applied: f32,
+}
+
+impl Default for AnchorUiOpacity {
+    fn default() -> Self {
+        Self {
+            fade: 1.0,
+            fog: 1.0,
+            occlusion: 1.0,
+            applied: 1.0,
+        }
+    }
+}
+
+impl AnchorUiOpacity {
+    /// The opacity from 0.0 to 1.0 the UI node is currently drawn with
+    pub fn value(&self) -> f32 {
+        (self.fade * self.fog * self.occlusion).clamp(0.0, 1.0)
+    }
+}
+
+/// The alpha values an entity had before the plugin started changing them
+#[derive(Component, Clone, Copy, PartialEq, Default)]
+struct AnchorUiBaseAlpha {
+    background: Option<f32>,
+    border: Option<[f32; 4]>,
+    text: Option<f32>,
+    image: Option<f32>,
+}
+
+/// The colors of an entity below an anchored UI node that its opacity is applied to
+#[derive(QueryData)]
+#[query_data(mutable)]
+pub(crate) struct ColoredQuery {
+    background: Option<&'static mut BackgroundColor>,
+    border: Option<&'static mut BorderColor>,
+    text: Option<&'static mut TextColor>,
+    image: Option<&'static mut ImageNode>,
+    base: Option<&'static AnchorUiBaseAlpha>,
+}
+
+impl ColoredQueryItem<'_, '_> {
+    /// If any of the colors was added or written to by something else since the opacity was last applied
+    fn is_changed(&self) -> bool {
+        self.background
+            .as_ref()
+            .is_some_and(|color| color.is_changed())
+            || self.border.as_ref().is_some_and(|color| color.is_changed())
+            || self.text.as_ref().is_some_and(|color| color.is_changed())
+            || self.image.as_ref().is_some_and(|image| image.is_changed())
+    }
+
+    fn capture(&self) -> AnchorUiBaseAlpha {
+        AnchorUiBaseAlpha {
+            background: self.background.as_ref().map(|color| color.0.alpha()),
+            border: self.border.as_ref().map(|border| {
+                [border.top, border.right, border.bottom, border.left].map(|color| color.alpha())
+            }),
+            text: self.text.as_ref().map(|color| color.0.alpha()),
+            image: self.image.as_ref().map(|image| image.color.alpha()),
+        }
+    }
+
+    fn apply(&mut self, base: &AnchorUiBaseAlpha, opacity: f32) {
+        if let (Some(color), Some(alpha)) = (self.background.as_mut(), base.background) {
+            color.0.set_alpha(alpha * opacity);
+        }
+        if let (Some(border), Some(alpha)) = (self.border.as_mut(), base.border) {
+            border.top.set_alpha(alpha[0] * opacity);
+            border.right.set_alpha(alpha[1] * opacity);
+            border.bottom.set_alpha(alpha[2] * opacity);
+            border.left.set_alpha(alpha[3] * opacity);
+        }
+        if let (Some(color), Some(alpha)) = (self.text.as_mut(), base.text) {
+            color.0.set_alpha(alpha * opacity);
+        }
+        if let (Some(image), Some(alpha)) = (self.image.as_mut(), base.image) {
+            image.color.set_alpha(alpha * opacity);
+        }
+    }
+}
+
+/// Moves 'fade' towards fully visible or fully hidden, returns if the UI node is still visible
+pub(crate) fn step_fade(
+    opacity: &mut AnchorUiOpacity,
+    fade: &AnchorFade,
+    hidden: bool,
+    dt: f32,
+) -> bool {
+    let rate = |duration: f32| {
+        if duration <= 0.0 {
+            f32::INFINITY
+        } else {
+            dt / duration
+        }
+    };
+    if hidden {
+        opacity.fade = (opacity.fade - rate(fade.fade_out)).max(0.0);
+        opacity.fade > 0.0
+    } else {
+        opacity.fade = (opacity.fade + rate(fade.fade_in)).min(1.0);
+        true
+    }
+}
+
+// the camera marker is only needed to find the 'DistanceFog'
+#[cfg_attr(
+    not(feature = "distance_fog"),
+    allow(clippy::extra_unused_type_parameters)
+)]
+pub(crate) fn system_depth_fog<C: Component>(
+    #[cfg(feature = "distance_fog")] cameras: Query<&DistanceFog, With<C>>,
+    uinodes: Query<(&mut AnchorUiOpacity, &AnchorUiProjection, &AnchorUiConfig)>,
+) {
+    #[cfg(feature = "distance_fog")]
+    let camera_fog = cameras.single().ok();
+
+    for (mut opacity, projection, config) in uinodes {
+        let Some(fog) = &config.fog else {
+            if opacity.fog != 1.0 {
+                opacity.fog = 1.0;
+            }
+            continue;
+        };
+        #[allow(unused_mut)]
+        let mut amount = fog.amount(projection.depth);
+        #[cfg(feature = "distance_fog")]
+        if let (true, Some(camera_fog)) = (fog.use_camera_fog, camera_fog) {
+            amount = camera_fog_amount(camera_fog, projection.depth);
+        }
+        let value = fog.opacity(amount);
+        if opacity.fog != value {
+            opacity.fog = value;
+        }
+    }
+}
+
+pub(crate) fn system_apply_opacity(
+    mut commands: Commands,
+    mut uinodes: Query<(Entity, &mut AnchorUiOpacity, Option<&LeaderLineSegments>)>,
+    children: Query<&Children>,
+    mut colored: Query<ColoredQuery>,
+) {
+    for (entity, mut opacity, leader_line) in uinodes.iter_mut() {
+        let value = opacity.value();
+        let unchanged = value == opacity.applied;
+        // fully opaque and already restored, nothing to do
+        if unchanged && value >= 1.0 {
+            continue;
+        }
+        // the leader line segments are not below the UI node, but should fade with it
+        let segments = leader_line.into_iter().flat_map(|segments| segments.iter());
+        for descendant in std::iter::once(entity)
+            .chain(children.iter_descendants(entity))
+            .chain(segments)
+        {
+            let Ok(mut item) = colored.get_mut(descendant) else {
+                continue;
+            };
+            // while see-through, new descendants and colors written by others still need the opacity
+            let changed = item.is_changed();
+            if unchanged && !changed {
+                continue;
+            }
+            let base = match item.base {
+                Some(base) if !changed => *base,
+                _ => item.capture(),
+            };
+            item.apply(&base, value);
+            if value >= 1.0 {
+                if item.base.is_some() {
+                    commands.entity(descendant).remove::<AnchorUiBaseAlpha>();
+                }
+            } else if item.base.copied() != Some(base) {
+                commands.entity(descendant).insert(base);
+            }
+        }
+        if !unchanged {
+            opacity.applied = value;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        tests::{spawn_anchored, test_app},
+        AnchorLeaderLine,
+    };
+
+    fn background_alpha(app: &App, entity: Entity) -> f32 {
+        app.world()
+            .get::<BackgroundColor>(entity)
+            .unwrap()
+            .0
+            .alpha()
+    }
+
+    #[test]
+    fn depth_fog_thickens_from_near_to_far() {
+        let fog = AnchorDepthFog::new(10.0, 20.0);
+        assert_eq!(fog.amount(5.0), 0.0);
+        assert_eq!(fog.amount(15.0), 0.5);
+        assert_eq!(fog.amount(25.0), 1.0);
+        assert_eq!(fog.with_min_opacity(0.25).opacity(fog.amount(25.0)), 0.25);
+    }
+
+    #[test]
+    fn depth_fog_without_range_is_a_wall() {
+        let fog = AnchorDepthFog::new(10.0, 10.0);
+        assert_eq!(fog.amount(9.0), 0.0);
+        assert_eq!(fog.amount(10.0), 1.0);
+    }
+
+    #[test]
+    fn opacity_reaches_leader_line() {
+        let mut app = test_app();
+        app.world_mut()
+            .spawn((Window::default(), bevy::window::PrimaryWindow));
+        let config = AnchorUiConfig::default()
+            .with_fog(AnchorDepthFog::new(-2.0, -1.0).with_min_opacity(0.5));
+        let line = AnchorLeaderLine {
+            color: Color::WHITE,
+            ..Default::default()
+        };
+        let uinode = spawn_anchored(&mut app, (config, line));
+        app.update();
+        app.update();
+        app.update();
+        let segments: Vec<Entity> = app
+            .world()
+            .get::<LeaderLineSegments>(uinode)
+            .unwrap()
+            .iter()
+            .collect();
+        assert!(!segments.is_empty());
+        for segment in segments {
+            assert_eq!(background_alpha(&app, segment), 0.5);
+        }
+    }
+
+    #[test]
+    fn opacity_reaches_children_spawned_later() {
+        let mut app = test_app();
+        // everything is beyond 'far', so the fog keeps the UI node at its minimum opacity
+        let config = AnchorUiConfig::default()
+            .with_fog(AnchorDepthFog::new(-2.0, -1.0).with_min_opacity(0.5));
+        let uinode = spawn_anchored(&mut app, (config, BackgroundColor(Color::WHITE)));
+        app.update();
+        assert_eq!(background_alpha(&app, uinode), 0.5);
+
+        let child = app
+            .world_mut()
+            .spawn((
+                Node::default(),
+                BackgroundColor(Color::WHITE),
+                ChildOf(uinode),
+            ))
+            .id();
+        app.update();
+        assert_eq!(background_alpha(&app, child), 0.5);
+
+        // a color written by the user is dimmed from its own alpha
+        app.world_mut()
+            .entity_mut(child)
+            .insert(BackgroundColor(Color::WHITE.with_alpha(0.8)));
+        app.update();
+        assert_eq!(background_alpha(&app, child), 0.4);
+        app.update();
+        assert_eq!(background_alpha(&app, child), 0.4);
+    }
+}
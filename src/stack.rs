@@ -0,0 +1,95 @@
+use bevy::prelude::*;
+
+use crate::{move_placed_node, AnchorUiProjection, AnchorUiVisibility, AnchoredUiNodes};
+
+/// Which way the UI nodes anchored to the same target are stacked, starting from the first one
+#[derive(Default, Reflect, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StackDirection {
+    #[default]
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+/// Put this on an entity with 'AnchoredUiNodes' to lay its anchored UI nodes out next to each other, instead of on top of each other
+///
+/// The first UI node is placed as usual, and each following one is placed next to the previous one in 'direction'.
+/// UI nodes are ordered by their 'AnchorUiStackOrder', and then by the order they were anchored in
+#[derive(Component, Reflect, Debug, Clone, Copy, PartialEq, Default)]
+pub struct AnchorUiStack {
+    /// Which way the stack grows
+    pub direction: StackDirection,
+    /// Space in logical pixels between two UI nodes in the stack
+    pub spacing: f32,
+}
+
+impl AnchorUiStack {
+    pub fn new(direction: StackDirection) -> Self {
+        Self {
+            direction,
+            spacing: 0.0,
+        }
+    }
+    pub fn with_spacing(mut self, spacing: f32) -> Self {
+        self.spacing = spacing;
+        self
+    }
+}
+
+/// Sort key of an anchored UI node within its 'AnchorUiStack', lower values are placed first
+///
+/// Nodes without this component have sort key 0
+#[derive(Component, Reflect, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct AnchorUiStackOrder(pub i32);
+
+pub(crate) fn system_stack_ui_nodes(
+    targets: Query<(&AnchorUiStack, &AnchoredUiNodes)>,
+    mut uinodes: Query<(
+        &mut Node,
+        &mut AnchorUiProjection,
+        &AnchorUiVisibility,
+        &Visibility,
+        Option<&AnchorUiStackOrder>,
+    )>,
+) {
+    for (stack, anchored) in targets.iter() {
+        let mut ordered: Vec<(AnchorUiStackOrder, Entity)> = anchored
+            .iter()
+            .filter_map(|entity| {
+                let (node, _, vis, visibility, order) = uinodes.get(entity).ok()?;
+                // hidden nodes should not leave a gap in the stack
+                (node.display != Display::None
+                    && *visibility != Visibility::Hidden
+                    && !vis.outside_camera
+                    && !vis.is_hidden())
+                .then(|| (order.copied().unwrap_or_default(), entity))
+            })
+            .collect();
+        // stable, so nodes with the same sort key keep the relationship order
+        ordered.sort_by_key(|(order, _)| *order);
+
+        let mut previous: Option<Rect> = None;
+        for (_, entity) in ordered {
+            let Ok((mut node, mut projection, _, _, _)) = uinodes.get_mut(entity) else {
+                continue;
+            };
+            let rect = projection.rect;
+            let Some(prev) = previous else {
+                previous = Some(rect);
+                continue;
+            };
+            let offset = match stack.direction {
+                StackDirection::Up => Vec2::new(0.0, prev.min.y - stack.spacing - rect.max.y),
+                StackDirection::Down => Vec2::new(0.0, prev.max.y + stack.spacing - rect.min.y),
+                StackDirection::Left => Vec2::new(prev.min.x - stack.spacing - rect.max.x, 0.0),
+                StackDirection::Right => Vec2::new(prev.max.x + stack.spacing - rect.min.x, 0.0),
+            };
+            if offset != Vec2::ZERO {
+                // marked as changed by 'system_mark_moved_ui_nodes' if it ends up somewhere new
+                move_placed_node(node.bypass_change_detection(), &mut projection, offset);
+            }
+            previous = Some(projection.rect);
+        }
+    }
+}
@@ -0,0 +1,252 @@
+use std::sync::Arc;
+
+use bevy::{ecs::query::QueryData, prelude::*};
+
+use crate::{
+    AnchorUiConfig, AnchorUiNode, AnchorUiProjection, AnchorUiVisibility, OutsideCameraTest,
+};
+
+/// What a 'VisibilityRule' can decide from, for one anchored UI node
+pub struct AnchorVisibilityContext<'a> {
+    /// The anchored UI node
+    pub uinode: Entity,
+    /// The entity the UI node is anchored to
+    pub target: Entity,
+    /// Where the UI node was last placed on screen
+    pub projection: &'a AnchorUiProjection,
+    /// If the target is outside of the camera, according to 'AnchorUiConfig::outside_camera_test'
+    pub outside_camera: bool,
+    /// If the target is visible, from its 'InheritedVisibility'
+    pub target_visible: bool,
+    /// If the 'RenderLayers' of the target overlap with those of the camera
+    pub on_camera_layers: bool,
+}
+
+/// A user defined rule, returns if the anchored UI node may be visible
+pub type AnchorVisibilityHook = Arc<dyn Fn(&AnchorVisibilityContext) -> bool + Send + Sync>;
+
+/// A condition that has to be met for an anchored UI node to be visible
+#[derive(Clone)]
+pub enum VisibilityRule {
+    /// Hidden while the target is projected outside of the camera, same as 'AnchorUiConfig::hide_outside_camera'
+    InsideCamera,
+    /// Hidden while the target is not visible, same as 'AnchorUiConfig::follow_visibility'
+    TargetVisible,
+    /// Hidden while the camera does not render the 'RenderLayers' of the target, same as 'AnchorUiConfig::follow_render_layers'
+    CameraLayers,
+    /// Hidden while the function returns false
+    ///
+    /// Called once per frame, once the visibility of the target is known. Earlier in the frame, when the plugin
+    /// leaves hidden UI nodes out of clustering, the budget and decluttering, the result of the previous frame is used
+    Custom(AnchorVisibilityHook),
+}
+
+impl VisibilityRule {
+    pub fn custom(rule: impl Fn(&AnchorVisibilityContext) -> bool + Send + Sync + 'static) -> Self {
+        Self::Custom(Arc::new(rule))
+    }
+
+    /// If the anchored UI node may be visible according to this rule
+    pub fn is_met(&self, context: &AnchorVisibilityContext) -> bool {
+        match self {
+            VisibilityRule::InsideCamera => !context.outside_camera,
+            VisibilityRule::TargetVisible => context.target_visible,
+            VisibilityRule::CameraLayers => context.on_camera_layers,
+            VisibilityRule::Custom(rule) => rule(context),
+        }
+    }
+}
+
+/// The rules that all have to be met for an anchored UI node to be visible
+///
+/// The rules from 'AnchorUiConfig::hide_outside_camera', 'AnchorUiConfig::follow_visibility' and 'AnchorUiConfig::follow_render_layers'
+/// are checked first, then these in order.
+/// Whether the UI node is shown is decided with this precedence:
+/// 1. 'AnchorUiVisibilityOverride', if present
+/// 2. the rules, any rule that is not met hides the UI node
+/// 3. the plugin itself, such as 'AnchorUiContainer', 'AnchorClusterGroup', 'AnchorUiBudget' and 'AnchorUiDeclutter'
+#[derive(Component, Clone, Default)]
+pub struct AnchorUiVisibilityPolicy {
+    pub rules: Vec<VisibilityRule>,
+}
+
+impl AnchorUiVisibilityPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn with_rule(mut self, rule: VisibilityRule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+    /// Adds a 'VisibilityRule::Custom' rule
+    pub fn with_custom(
+        self,
+        rule: impl Fn(&AnchorVisibilityContext) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.with_rule(VisibilityRule::custom(rule))
+    }
+}
+
+/// Forces an anchored UI node to be visible or hidden, regardless of any rule or of the plugin
+///
+/// Can be inserted and removed from your own systems, as long as they run before 'AnchorUiSystemSet::UpdateVisibility'.
+/// Once removed, the rules and the plugin decide again, and a UI node the plugin hid is shown when nothing hides it
+#[derive(Component, Reflect, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnchorUiVisibilityOverride {
+    Visible,
+    Hidden,
+}
+
+/// What evaluating the visibility rules needs from an anchored UI node
+#[derive(QueryData)]
+#[query_data(mutable)]
+pub(crate) struct PolicyQuery {
+    entity: Entity,
+    visibility: &'static mut AnchorUiVisibility,
+    anchor: &'static AnchorUiNode,
+    config: &'static AnchorUiConfig,
+    projection: &'static AnchorUiProjection,
+    policy: Option<&'static AnchorUiVisibilityPolicy>,
+    visibility_override: Option<&'static AnchorUiVisibilityOverride>,
+}
+
+/// Evaluates the rules into 'AnchorUiVisibility'
+///
+/// Runs once after the UI nodes are moved, so the plugin can leave out hidden nodes when laying out,
+/// and again once the visibility of the targets is known for this frame. Only the last run, with 'FINAL',
+/// calls the 'VisibilityRule::Custom' hooks
+pub(crate) fn system_evaluate_visibility_rules<const FINAL: bool>(uinodes: Query<PolicyQuery>) {
+    for mut uinode in uinodes {
+        let forced = uinode
+            .visibility_override
+            .map(|forced| *forced == AnchorUiVisibilityOverride::Hidden);
+
+        let shorthand = [
+            uinode
+                .config
+                .hide_outside_camera
+                .then_some(&VisibilityRule::InsideCamera),
+            uinode
+                .config
+                .follow_visibility
+                .then_some(&VisibilityRule::TargetVisible),
+            uinode
+                .config
+                .follow_render_layers
+                .then_some(&VisibilityRule::CameraLayers),
+        ];
+        let context = AnchorVisibilityContext {
+            uinode: uinode.entity,
+            target: uinode.anchor.target,
+            projection: uinode.projection,
+            outside_camera: match uinode.config.outside_camera_test {
+                OutsideCameraTest::Point => uinode.visibility.outside_camera,
+                // the UI node can still not be placed while the anchored location is behind the camera
+                OutsideCameraTest::ViewVisibility => {
+                    uinode.visibility.outside_camera
+                        || uinode.visibility.target_in_view == Some(false)
+                }
+            },
+            target_visible: uinode.visibility.anchor_visible,
+            on_camera_layers: uinode.visibility.on_camera_layers,
+        };
+        let mut rules = None;
+        let mut custom_hidden = false;
+        for rule in shorthand.into_iter().flatten().chain(
+            uinode
+                .policy
+                .into_iter()
+                .flat_map(|policy| policy.rules.iter()),
+        ) {
+            let met = match rule {
+                VisibilityRule::Custom(_) if !FINAL => !uinode.visibility.custom_hidden,
+                _ => rule.is_met(&context),
+            };
+            rules = Some(!met);
+            if !met {
+                custom_hidden = matches!(rule, VisibilityRule::Custom(_));
+                break;
+            }
+        }
+
+        if FINAL && uinode.visibility.custom_hidden != custom_hidden {
+            uinode.visibility.custom_hidden = custom_hidden;
+        }
+        if uinode.visibility.forced != forced {
+            uinode.visibility.forced = forced;
+        }
+        if uinode.visibility.rules_hidden != rules {
+            uinode.visibility.rules_hidden = rules;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::{
+        tests::{spawn_anchored, test_app, visibility},
+        AnchorUiBudget,
+    };
+
+    fn hide_all() -> AnchorUiVisibilityPolicy {
+        AnchorUiVisibilityPolicy::new().with_custom(|_| false)
+    }
+
+    #[test]
+    fn override_wins_over_rules_and_plugin() {
+        let mut app = test_app();
+        app.insert_resource(AnchorUiBudget::new(0));
+        let forced_visible =
+            spawn_anchored(&mut app, (hide_all(), AnchorUiVisibilityOverride::Visible));
+        let forced_hidden = spawn_anchored(&mut app, AnchorUiVisibilityOverride::Hidden);
+        app.update();
+        app.update();
+        assert_eq!(visibility(&app, forced_visible), Visibility::Visible);
+        assert_eq!(visibility(&app, forced_hidden), Visibility::Hidden);
+    }
+
+    #[test]
+    fn rules_hide_before_the_plugin() {
+        let mut app = test_app();
+        app.insert_resource(AnchorUiBudget::new(1));
+        // hidden by its rule, so it does not take up the budget
+        let hidden = spawn_anchored(&mut app, hide_all());
+        let shown = spawn_anchored(&mut app, ());
+        app.update();
+        app.update();
+        assert_eq!(visibility(&app, hidden), Visibility::Hidden);
+        assert_eq!(visibility(&app, shown), Visibility::Visible);
+    }
+
+    #[test]
+    fn plugin_hides_when_rules_are_met() {
+        let mut app = test_app();
+        app.insert_resource(AnchorUiBudget::new(0));
+        let uinode = spawn_anchored(
+            &mut app,
+            AnchorUiVisibilityPolicy::new().with_custom(|_| true),
+        );
+        app.update();
+        assert_eq!(visibility(&app, uinode), Visibility::Hidden);
+    }
+
+    #[test]
+    fn custom_rules_are_called_once_per_frame() {
+        let mut app = test_app();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counted = calls.clone();
+        spawn_anchored(
+            &mut app,
+            AnchorUiVisibilityPolicy::new().with_custom(move |_| {
+                counted.fetch_add(1, Ordering::Relaxed);
+                true
+            }),
+        );
+        app.update();
+        app.update();
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+    }
+}